@@ -0,0 +1,144 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+// the partitions a CloudFormation stack can live in
+pub const PARTITIONS: [&str; 3] = ["aws", "aws-cn", "aws-us-gov"];
+
+/*
+ * The ARN of a CloudFormation stack, as sent in the StackId of every request event:
+ *   arn:<partition>:cloudformation:<region>:<account id>:stack/<stack name>/<stack uuid>
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StackArn {
+    partition: String,
+    region: String,
+    account_id: String,
+    stack_name: String,
+    stack_uuid: String,
+}
+
+impl StackArn {
+    pub fn partition(&self) -> &str { &self.partition }
+    pub fn region(&self) -> &str { &self.region }
+    pub fn account_id(&self) -> &str { &self.account_id }
+    pub fn stack_name(&self) -> &str { &self.stack_name }
+    pub fn stack_uuid(&self) -> &str { &self.stack_uuid }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum StackArnError {
+    /// the value does not have the six colon separated fields of an ARN, starting with "arn"
+    NotAnArn(String),
+
+    /// the partition is not one of aws, aws-cn, aws-us-gov
+    UnsupportedPartition(String),
+
+    /// the ARN is not for the cloudformation service, the service is given
+    NotCloudFormation(String),
+
+    /// the region is empty
+    MissingRegion(),
+
+    /// the account id is not twelve digits
+    InvalidAccountId(String),
+
+    /// the resource is not of the form stack/<stack name>/<stack uuid>
+    InvalidResource(String),
+}
+
+impl fmt::Debug for StackArnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAnArn(value) => write!(f, "'{}' is not an ARN", value),
+            Self::UnsupportedPartition(partition) =>
+                write!(f, "unsupported partition '{}', expected one of {}", partition, PARTITIONS.join(", ")),
+            Self::NotCloudFormation(service) => write!(f, "expected a cloudformation ARN, found service '{}'", service),
+            Self::MissingRegion() => write!(f, "the ARN has no region"),
+            Self::InvalidAccountId(account_id) => write!(f, "invalid account id '{}', expected twelve digits", account_id),
+            Self::InvalidResource(resource) =>
+                write!(f, "invalid resource '{}', expected stack/<stack name>/<stack uuid>", resource),
+        }
+    }
+}
+
+impl fmt::Display for StackArnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self, f)
+    }
+}
+
+impl error::Error for StackArnError {
+    fn description(&self) -> &str {
+        match *self {
+            Self::NotAnArn(_) => "value is not an ARN",
+            Self::UnsupportedPartition(_) => "unsupported ARN partition",
+            Self::NotCloudFormation(_) => "ARN is not for a CloudFormation stack",
+            Self::MissingRegion() => "ARN has no region",
+            Self::InvalidAccountId(_) => "invalid ARN account id",
+            Self::InvalidResource(_) => "ARN resource is not a stack",
+        }
+    }
+}
+
+impl FromStr for StackArn {
+    type Err = StackArnError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.splitn(6, ':').collect();
+        let (partition, service, region, account_id, resource) = match fields[..] {
+            ["arn", partition, service, region, account_id, resource] => (partition, service, region, account_id, resource),
+            _ => return Err(StackArnError::NotAnArn(s.to_string())),
+        };
+        if !PARTITIONS.contains(&partition) {
+            return Err(StackArnError::UnsupportedPartition(partition.to_string()));
+        }
+        if service != "cloudformation" {
+            return Err(StackArnError::NotCloudFormation(service.to_string()));
+        }
+        if region.is_empty() {
+            return Err(StackArnError::MissingRegion());
+        }
+        if account_id.len() != 12 || !account_id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(StackArnError::InvalidAccountId(account_id.to_string()));
+        }
+        let (stack_name, stack_uuid) = match resource.split('/').collect::<Vec<&str>>()[..] {
+            ["stack", stack_name, stack_uuid] if !stack_name.is_empty() && !stack_uuid.is_empty() => (stack_name, stack_uuid),
+            _ => return Err(StackArnError::InvalidResource(resource.to_string())),
+        };
+
+        Ok(StackArn {
+            partition: partition.to_string(),
+            region: region.to_string(),
+            account_id: account_id.to_string(),
+            stack_name: stack_name.to_string(),
+            stack_uuid: stack_uuid.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for StackArn {
+    type Error = StackArnError;
+    fn try_from(value: String) -> Result<Self, Self::Error> { value.parse() }
+}
+
+impl fmt::Display for StackArn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "arn:{}:cloudformation:{}:{}:stack/{}/{}",
+            self.partition, self.region, self.account_id, self.stack_name, self.stack_uuid)
+    }
+}
+
+// serialized as the ARN string, as it appears in the StackId
+impl serde::Serialize for StackArn {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        serializer.collect_str(self)
+    }
+}
+
+impl <'de> serde::Deserialize<'de> for StackArn {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * Serde helpers for values CloudFormation delivers as strings, accepting the native JSON form as well.
+ * Use them with #[serde(with = "...")] on fields of the events or of provider property types:
+ *
+ *   #[derive(Deserialize, Serialize)]
+ *   struct Properties {
+ *       #[serde(with = "coerce::from_str")]
+ *       replicas: u32,
+ *       #[serde(default, with = "coerce::option_from_str")]
+ *       enabled: Option<bool>,
+ *       #[serde(with = "coerce::duration_seconds")]
+ *       timeout: Duration,
+ *   }
+ *
+ * Parse failures are reported against the field, so serde_path_to_error names it in the FAILED reason.
+ * Unit-only enums deriving Deserialize already accept their variant names as strings and need no helper.
+ */
+use serde::de::{ self, Deserializer, Visitor };
+use serde::Serializer;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::time::Duration;
+
+struct FromStrVisitor<T>(PhantomData<T>);
+
+impl <T> FromStrVisitor<T>
+where T: FromStr, T::Err: fmt::Display
+{
+    fn parse<E>(v: &str) -> Result<T, E> where E: de::Error {
+        // the value itself is left out, the field may be sensitive and the message ends up in the FAILED Reason
+        v.trim().parse::<T>()
+            .map_err(|err| E::custom(format!("unable to parse as {}: {}", std::any::type_name::<T>(), err)))
+    }
+}
+
+impl <'de, T> Visitor<'de> for FromStrVisitor<T>
+where T: FromStr, T::Err: fmt::Display
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a boolean, a number or a string")
+    }
+    // native JSON scalars are parsed from their string form, so `true`, `42` and `3.5` read the same as "true", "42" and "3.5"
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> where E: de::Error {
+        Self::parse(&v.to_string())
+    }
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> where E: de::Error {
+        Self::parse(&v.to_string())
+    }
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> where E: de::Error {
+        Self::parse(&v.to_string())
+    }
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> where E: de::Error {
+        Self::parse(&v.to_string())
+    }
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: de::Error {
+        Self::parse(v)
+    }
+}
+
+/// Any FromStr type: booleans, integers, floats, or enums implementing FromStr, given natively or as a string.
+/// Serialized as a string via Display, as CloudFormation sends it.
+pub mod from_str {
+    use super::*;
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>, T: FromStr, T::Err: fmt::Display {
+        deserializer.deserialize_any(FromStrVisitor(PhantomData))
+    }
+    pub fn serialize<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer, T: fmt::Display {
+        serializer.collect_str(value)
+    }
+}
+
+/// An optional FromStr type, see `from_str`. Combine with #[serde(default)] so the field may be absent.
+pub mod option_from_str {
+    use super::*;
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where D: Deserializer<'de>, T: FromStr, T::Err: fmt::Display {
+        struct OptionVisitor<T>(PhantomData<T>);
+        impl <'de, T> Visitor<'de> for OptionVisitor<T>
+        where T: FromStr, T::Err: fmt::Display
+        {
+            type Value = Option<T>;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                FromStrVisitor::<T>(PhantomData).expecting(f)
+            }
+            fn visit_none<E>(self) -> Result<Self::Value, E> where E: de::Error {
+                Ok(None)
+            }
+            fn visit_unit<E>(self) -> Result<Self::Value, E> where E: de::Error {
+                Ok(None)
+            }
+            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error> where D: Deserializer<'de> {
+                super::from_str::deserialize(deserializer).map(Some)
+            }
+        }
+        deserializer.deserialize_option(OptionVisitor(PhantomData))
+    }
+    pub fn serialize<S, T>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer, T: fmt::Display {
+        match value {
+            Some(value) => super::from_str::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+struct SecondsVisitor;
+
+impl <'de> Visitor<'de> for SecondsVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a whole number of seconds, or a string containing one")
+    }
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> where E: de::Error {
+        Ok(Duration::from_secs(v))
+    }
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> where E: de::Error {
+        u64::try_from(v)
+            .map(Duration::from_secs)
+            .map_err(|_| E::invalid_value(de::Unexpected::Other("a negative number"), &self))
+    }
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: de::Error {
+        v.trim().parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|_| E::invalid_value(de::Unexpected::Other("a string that is not a whole number"), &self))
+    }
+}
+
+/// A Duration given as whole seconds, e.g. `300` or `"300"`. Serialized as a string, as CloudFormation sends it.
+pub mod duration_seconds {
+    use super::*;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_any(SecondsVisitor)
+    }
+    pub fn serialize<S>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&value.as_secs().to_string())
+    }
+}
+
+/// An optional Duration given as whole seconds, see `duration_seconds`.
+pub mod option_duration_seconds {
+    use super::*;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error> where D: Deserializer<'de> {
+        struct OptionVisitor;
+        impl <'de> Visitor<'de> for OptionVisitor {
+            type Value = Option<Duration>;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                SecondsVisitor.expecting(f)
+            }
+            fn visit_none<E>(self) -> Result<Self::Value, E> where E: de::Error {
+                Ok(None)
+            }
+            fn visit_unit<E>(self) -> Result<Self::Value, E> where E: de::Error {
+                Ok(None)
+            }
+            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error> where D: Deserializer<'de> {
+                super::duration_seconds::deserialize(deserializer).map(Some)
+            }
+        }
+        deserializer.deserialize_option(OptionVisitor)
+    }
+    pub fn serialize<S>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match value {
+            Some(value) => super::duration_seconds::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::ProviderRequestUpdateEvent;
+use serde_json::Value;
+use std::fmt;
+
+/// A single difference between the old and new properties, at a JSON path such as `Rules[2].Port`,
+/// or `.` when the properties as a whole are not objects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyChange {
+    /// present only in the new properties
+    Added { path: String, value: Value },
+
+    /// present only in the old properties
+    Removed { path: String, value: Value },
+
+    /// present in both, with a different value
+    Modified { path: String, old: Value, new: Value },
+}
+
+impl PropertyChange {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Added { path, .. } => path,
+            Self::Removed { path, .. } => path,
+            Self::Modified { path, .. } => path,
+        }
+    }
+}
+
+// includes the values, which may be sensitive. see PropertyDiff::summary for a form safe to log
+impl fmt::Display for PropertyChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Added { path, value } => write!(f, "+ {}: {}", path, value),
+            Self::Removed { path, value } => write!(f, "- {}: {}", path, value),
+            Self::Modified { path, old, new } => write!(f, "~ {}: {} -> {}", path, old, new),
+        }
+    }
+}
+
+/*
+ * The changes between the OldResourceProperties and ResourceProperties of an Update, down to the
+ * individual fields of nested objects and the elements of arrays, which are compared by index.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PropertyDiff {
+    pub changes: Vec<PropertyChange>,
+}
+
+impl PropertyDiff {
+    /// the changes between two raw property values, either of which may be absent
+    pub fn between(old: Option<&Value>, new: Option<&Value>) -> Self {
+        // absent properties are compared as an empty object, so each field is reported on its own
+        let empty = Value::Object(serde_json::Map::new());
+        let mut changes = Vec::new();
+        diff(String::new(), old.or(Some(&empty)), new.or(Some(&empty)), &mut changes);
+        PropertyDiff { changes }
+    }
+
+    /// the changes between two typed property values, compared in their serialized form
+    pub fn between_typed<O, T>(old: Option<&O>, new: Option<&T>) -> Result<Self, serde_json::Error>
+    where O: serde::Serialize,
+          T: serde::Serialize,
+    {
+        let old = old.map(serde_json::to_value).transpose()?;
+        let new = new.map(serde_json::to_value).transpose()?;
+        Ok(PropertyDiff::between(old.as_ref(), new.as_ref()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// whether the property at the path, or anything nested within it, changed
+    pub fn changed(&self, path: &str) -> bool {
+        self.changes.iter().any(|change| {
+            let changed = change.path();
+            changed == path || path.is_empty()
+                || matches!(changed.strip_prefix(path), Some(rest) if rest.starts_with('.') || rest.starts_with('['))
+        })
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &PropertyChange> {
+        self.changes.iter().filter(|change| matches!(change, PropertyChange::Added { .. }))
+    }
+    pub fn removed(&self) -> impl Iterator<Item = &PropertyChange> {
+        self.changes.iter().filter(|change| matches!(change, PropertyChange::Removed { .. }))
+    }
+    pub fn modified(&self) -> impl Iterator<Item = &PropertyChange> {
+        self.changes.iter().filter(|change| matches!(change, PropertyChange::Modified { .. }))
+    }
+
+    /// the changed paths without their values, e.g. `+Tags[1] ~Name -Description`, safe to log
+    pub fn summary(&self) -> String {
+        if self.changes.is_empty() {
+            return "no changes".to_string();
+        }
+        self.changes.iter()
+            .map(|change| match change {
+                PropertyChange::Added { path, .. } => format!("+{}", path),
+                PropertyChange::Removed { path, .. } => format!("-{}", path),
+                PropertyChange::Modified { path, .. } => format!("~{}", path),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn child(path: &str, name: &str) -> String {
+    match path == "." {
+        true => name.to_string(),
+        false => format!("{}.{}", path, name),
+    }
+}
+
+fn diff(path: String, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<PropertyChange>) {
+    let path = match path.is_empty() {
+        true => ".".to_string(),
+        false => path,
+    };
+    match (old, new) {
+        (None, None) => {},
+        (None, Some(new)) => changes.push(PropertyChange::Added { path, value: new.clone() }),
+        (Some(old), None) => changes.push(PropertyChange::Removed { path, value: old.clone() }),
+        (Some(Value::Object(old)), Some(Value::Object(new))) => {
+            for (name, old_value) in old {
+                diff(child(&path, name), Some(old_value), new.get(name), changes);
+            }
+            for (name, new_value) in new.iter().filter(|(name, _)| !old.contains_key(*name)) {
+                diff(child(&path, name), None, Some(new_value), changes);
+            }
+        },
+        (Some(Value::Array(old)), Some(Value::Array(new))) => {
+            for index in 0..old.len().max(new.len()) {
+                diff(format!("{}[{}]", path.trim_start_matches('.'), index), old.get(index), new.get(index), changes);
+            }
+        },
+        (Some(old), Some(new)) if old != new =>
+            changes.push(PropertyChange::Modified { path, old: old.clone(), new: new.clone() }),
+        (Some(_), Some(_)) => {},
+    }
+}
+
+impl <T, O> ProviderRequestUpdateEvent<T, O>
+where T: serde::Serialize,
+      O: serde::Serialize,
+{
+    /// what the Update changes, from the OldResourceProperties to the ResourceProperties
+    pub fn property_diff(&self) -> Result<PropertyDiff, serde_json::Error> {
+        PropertyDiff::between_typed(self.old_resource_properties.as_ref(), self.resource_properties.as_ref())
+    }
+}
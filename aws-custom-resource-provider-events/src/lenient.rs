@@ -0,0 +1,63 @@
+use serde::de::{DeserializeOwned, Deserializer};
+use serde::ser::Serializer;
+use serde_json::Value;
+
+/*
+ * Properties that deserialize as T when they can, and are kept as the raw JSON when they cannot.
+ * Meant for the OldResourceProperties of an Update, e.g. ProviderRequestUpdateEvent<T, Lenient<T>>,
+ * which were declared against the property schema of an earlier version of the provider.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lenient<T> {
+    /// the properties matched T
+    Typed(T),
+
+    /// the properties as received, which did not match T
+    Raw(Value),
+}
+
+impl <T> Lenient<T> {
+    pub fn is_typed(&self) -> bool {
+        matches!(self, Lenient::Typed(_))
+    }
+    pub fn typed(&self) -> Option<&T> {
+        match self {
+            Lenient::Typed(typed) => Some(typed),
+            Lenient::Raw(_) => None,
+        }
+    }
+    pub fn into_typed(self) -> Option<T> {
+        match self {
+            Lenient::Typed(typed) => Some(typed),
+            Lenient::Raw(_) => None,
+        }
+    }
+    pub fn raw(&self) -> Option<&Value> {
+        match self {
+            Lenient::Typed(_) => None,
+            Lenient::Raw(raw) => Some(raw),
+        }
+    }
+}
+
+impl <'de, T> serde::Deserialize<'de> for Lenient<T>
+where T: DeserializeOwned {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let value = Value::deserialize(deserializer)?;
+        Ok(match T::deserialize(&value) {
+            Ok(typed) => Lenient::Typed(typed),
+            Err(_) => Lenient::Raw(value),
+        })
+    }
+}
+
+// serialized as the properties themselves, typed or raw
+impl <T> serde::Serialize for Lenient<T>
+where T: serde::Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match self {
+            Lenient::Typed(typed) => typed.serialize(serializer),
+            Lenient::Raw(raw) => raw.serialize(serializer),
+        }
+    }
+}
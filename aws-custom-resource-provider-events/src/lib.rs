@@ -1,5 +1,20 @@
 use serde_derive::{Deserialize,Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::time::Duration;
+
+mod properties;
+pub mod coerce;
+pub mod arn;
+pub mod size;
+pub mod physical_id;
+pub mod diff;
+pub mod lenient;
+
+pub use arn::{StackArn, StackArnError};
+pub use size::{OversizeStrategy, ResponseTooLarge, MAX_RESPONSE_SIZE};
+pub use diff::{PropertyChange, PropertyDiff};
+pub use lenient::Lenient;
+pub use physical_id::{PhysicalIdStrategy, PhysicalIdError, MAX_PHYSICAL_RESOURCE_ID_SIZE};
 
 
 // see: https://docs.aws.amazon.com/AWSCloudFormation/latest/UserGuide/crpg-ref-requests.html#crpg-ref-request-fields
@@ -14,6 +29,37 @@ pub fn request_type(event: &Value) -> Option<RequestType> {
     })
 }
 
+/*
+ * The ServiceTimeout of a raw request event, in the limits CloudFormation allows (1 to 3600 seconds).
+ * It is read from the top-level field, falling back to the copy CloudFormation places in ResourceProperties.
+ */
+pub fn service_timeout(event: &Value) -> Option<Duration> {
+    event.get("ServiceTimeout")
+        .or_else(|| event.get("ResourceProperties").and_then(|p| p.get("ServiceTimeout")))
+        .and_then(|v| coerce::duration_seconds::deserialize(v).ok())
+        .filter(|timeout| (1..=3600).contains(&timeout.as_secs()))
+}
+
+/*
+ * The raw request event with the service properties copied to the top level from ResourceProperties,
+ * where CloudFormation may send only the copy. Deserialize this rather than the event as received, so the
+ * typed service_token and service_timeout are set either way; top-level values are kept as they are.
+ */
+pub fn with_service_properties(event: &Value) -> Value {
+    let mut event = event.clone();
+    let copies = event.get("ResourceProperties")
+        .map(|properties| properties::SERVICE_PROPERTIES.iter()
+            .filter_map(|name| properties.get(*name).map(|value| (name.to_string(), value.clone())))
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+    if let Some(fields) = event.as_object_mut() {
+        for (name, value) in copies {
+            fields.entry(name).or_insert(value);
+        }
+    }
+    event
+}
+
 pub trait ProviderRequestEventDetails {
     fn request_type(&self) -> RequestType;
     fn response_url(&self) -> String;
@@ -21,6 +67,11 @@ pub trait ProviderRequestEventDetails {
     fn request_id(&self) -> String;
     fn resource_type(&self) -> String;
     fn logical_resource_id(&self) -> String;
+    // the PhysicalResourceId CloudFormation sent, only Update and Delete events carry one
+    fn physical_resource_id(&self) -> Option<String>;
+
+    // the StackId parsed into its partition, region, account id, stack name and stack uuid
+    fn stack_arn(&self) -> Result<StackArn, StackArnError> { self.stack_id().parse() }
 }
 
 
@@ -34,13 +85,21 @@ pub enum RequestType {
     Delete,
 }
 
+// defaults for the RequestType field of each event, which is consumed as the tag when
+// deserializing a ProviderRequestEvent.
+impl RequestType {
+    fn create() -> Self { RequestType::Create }
+    fn update() -> Self { RequestType::Update }
+    fn delete() -> Self { RequestType::Delete }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ProviderRequestCreateEvent<T> {
     /*
      * The request type is set by the AWS CloudFormation stack operation (create-stack, update-stack, or delete-stack)
      * that was initiated by the template developer for the stack that contains the custom resource.
      */
-    #[serde(rename = "RequestType")]
+    #[serde(rename = "RequestType", default = "RequestType::create")]
     pub request_type: RequestType,
 
     /*
@@ -81,17 +140,43 @@ pub struct ProviderRequestCreateEvent<T> {
      * This field contains the contents of the Properties object sent by the template developer.
      * Its contents are defined by the custom resource provider.
      */
-    #[serde(rename = "ResourceProperties")]
+    #[serde(rename = "ResourceProperties", default = "Option::default", deserialize_with = "properties::deserialize")]
+    #[serde(bound(deserialize = "T: serde::Deserialize<'de>"))]
     pub resource_properties: Option<T>,
+
+    /*
+     * The ARN of the function or topic the request was sent to, as declared by the template developer.
+     * CloudFormation also copies it into ResourceProperties, from where it is removed before deserialization.
+     * When only the copy was sent, see with_service_properties().
+     */
+    #[serde(rename = "ServiceToken", default, skip_serializing_if = "Option::is_none")]
+    pub service_token: Option<String>,
+
+    /*
+     * The maximum time the template developer allows the custom resource operation to take, sent in seconds.
+     * Like ServiceToken, it is removed from ResourceProperties before deserialization, see with_service_properties().
+     */
+    #[serde(rename = "ServiceTimeout", default, skip_serializing_if = "Option::is_none", with = "coerce::option_duration_seconds")]
+    pub service_timeout: Option<Duration>,
+
+    /*
+     * Any other top-level fields CloudFormation sent with the request, kept as they were received.
+     */
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+/*
+ * The OldResourceProperties may have a type O of their own, so an Update from properties declared for an
+ * earlier version of the provider still deserializes, e.g. with O = Lenient<T> or serde_json::Value.
+ */
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-pub struct ProviderRequestUpdateEvent<T> {
+pub struct ProviderRequestUpdateEvent<T, O = T> {
     /*
      * The request type is set by the AWS CloudFormation stack operation (create-stack, update-stack, or delete-stack)
      * that was initiated by the template developer for the stack that contains the custom resource.
      */
-    #[serde(rename = "RequestType")]
+    #[serde(rename = "RequestType", default = "RequestType::update")]
     pub request_type: RequestType,
 
     /*
@@ -140,15 +225,38 @@ pub struct ProviderRequestUpdateEvent<T> {
      * This field contains the contents of the Properties object sent by the template developer.
      * Its contents are defined by the custom resource provider.
      */
-    #[serde(rename = "ResourceProperties")]
+    #[serde(rename = "ResourceProperties", default = "Option::default", deserialize_with = "properties::deserialize")]
+    #[serde(bound(deserialize = "T: serde::Deserialize<'de>"))]
     pub resource_properties: Option<T>,
    
     /*
      * Used only for Update requests.
      * Contains the resource properties that were declared previous to the update request.
      */
-    #[serde(rename = "OldResourceProperties")]
-    pub old_resource_properties: Option<T>,
+    #[serde(rename = "OldResourceProperties", default = "Option::default", deserialize_with = "properties::deserialize")]
+    #[serde(bound(deserialize = "O: serde::Deserialize<'de>"))]
+    pub old_resource_properties: Option<O>,
+
+    /*
+     * The ARN of the function or topic the request was sent to, as declared by the template developer.
+     * CloudFormation also copies it into ResourceProperties, from where it is removed before deserialization.
+     * When only the copy was sent, see with_service_properties().
+     */
+    #[serde(rename = "ServiceToken", default, skip_serializing_if = "Option::is_none")]
+    pub service_token: Option<String>,
+
+    /*
+     * The maximum time the template developer allows the custom resource operation to take, sent in seconds.
+     * Like ServiceToken, it is removed from ResourceProperties before deserialization, see with_service_properties().
+     */
+    #[serde(rename = "ServiceTimeout", default, skip_serializing_if = "Option::is_none", with = "coerce::option_duration_seconds")]
+    pub service_timeout: Option<Duration>,
+
+    /*
+     * Any other top-level fields CloudFormation sent with the request, kept as they were received.
+     */
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 
@@ -158,7 +266,7 @@ pub struct ProviderRequestDeleteEvent<T> {
      * The request type is set by the AWS CloudFormation stack operation (create-stack, update-stack, or delete-stack)
      * that was initiated by the template developer for the stack that contains the custom resource.
      */
-    #[serde(rename = "RequestType")]
+    #[serde(rename = "RequestType", default = "RequestType::delete")]
     pub request_type: RequestType,
 
     /*
@@ -207,9 +315,30 @@ pub struct ProviderRequestDeleteEvent<T> {
      * This field contains the contents of the Properties object sent by the template developer.
      * Its contents are defined by the custom resource provider.
      */
-    #[serde(rename = "ResourceProperties")]
+    #[serde(rename = "ResourceProperties", default = "Option::default", deserialize_with = "properties::deserialize")]
+    #[serde(bound(deserialize = "T: serde::Deserialize<'de>"))]
     pub resource_properties: Option<T>,
-   
+
+    /*
+     * The ARN of the function or topic the request was sent to, as declared by the template developer.
+     * CloudFormation also copies it into ResourceProperties, from where it is removed before deserialization.
+     * When only the copy was sent, see with_service_properties().
+     */
+    #[serde(rename = "ServiceToken", default, skip_serializing_if = "Option::is_none")]
+    pub service_token: Option<String>,
+
+    /*
+     * The maximum time the template developer allows the custom resource operation to take, sent in seconds.
+     * Like ServiceToken, it is removed from ResourceProperties before deserialization, see with_service_properties().
+     */
+    #[serde(rename = "ServiceTimeout", default, skip_serializing_if = "Option::is_none", with = "coerce::option_duration_seconds")]
+    pub service_timeout: Option<Duration>,
+
+    /*
+     * Any other top-level fields CloudFormation sent with the request, kept as they were received.
+     */
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 
@@ -220,17 +349,19 @@ impl <T> ProviderRequestEventDetails for ProviderRequestCreateEvent<T>  {
     fn request_id(&self) -> String { self.request_id.clone() }
     fn resource_type(&self) -> String { self.resource_type.clone() }
     fn logical_resource_id(&self) -> String { self.logical_resource_id.clone() }
+    fn physical_resource_id(&self) -> Option<String> { None }
 
 }
 
 
-impl <T> ProviderRequestEventDetails for ProviderRequestUpdateEvent<T>  {
+impl <T, O> ProviderRequestEventDetails for ProviderRequestUpdateEvent<T, O>  {
     fn request_type(&self) -> RequestType { self.request_type.clone() }
     fn response_url(&self) -> String { self.response_url.clone() }
     fn stack_id(&self) -> String { self.stack_id.clone() }
     fn request_id(&self) -> String { self.request_id.clone() }
     fn resource_type(&self) -> String { self.resource_type.clone() }
     fn logical_resource_id(&self) -> String { self.logical_resource_id.clone() }
+    fn physical_resource_id(&self) -> Option<String> { Some(self.physical_resource_id.clone()) }
 
 }
 
@@ -242,75 +373,74 @@ impl <T> ProviderRequestEventDetails for ProviderRequestDeleteEvent<T>  {
     fn request_id(&self) -> String { self.request_id.clone() }
     fn resource_type(&self) -> String { self.resource_type.clone() }
     fn logical_resource_id(&self) -> String { self.logical_resource_id.clone() }
+    fn physical_resource_id(&self) -> Option<String> { Some(self.physical_resource_id.clone()) }
 
 }
 
 
-//#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-//pub struct ProviderRequestEvent<T> {
-//    /*
-//     * The request type is set by the AWS CloudFormation stack operation (create-stack, update-stack, or delete-stack)
-//     * that was initiated by the template developer for the stack that contains the custom resource.
-//     */
-//    #[serde(rename = "RequestType")]
-//    pub request_type: RequestType,
-//
-//    /*
-//     * The response URL identifies a presigned S3 bucket that receives responses from the custom resource provider to AWS CloudFormation.
-//     */
-//    #[serde(rename = "ResponseURL")]
-//    pub response_url: String,
-//
-//    /*
-//     * The Amazon Resource Name (ARN) that identifies the stack that contains the custom resource.
-//     * Combining the StackId with the RequestId forms a value that you can use to uniquely identify a request on a particular custom resource.
-//     */
-//    #[serde(rename = "StackId")]
-//    pub stack_id: String,
-//
-//    /*
-//     * A unique ID for the request.
-//     * Combining the StackId with the RequestId forms a value that you can use to uniquely identify a request on a particular custom resource.
-//     */
-//    #[serde(rename = "RequestId")]
-//    pub request_id: String,
-//
-//    /*
-//     * The template developer-chosen resource type of the custom resource in the AWS CloudFormation template.
-//     * Custom resource type names can be up to 60 characters long and can include alphanumeric and the following characters: _@-.
-//     */
-//    #[serde(rename = "ResourceType")]
-//    pub resource_type: String,
-//
-//
-//    /*
-//     * The template developer-chosen name (logical ID) of the custom resource in the AWS CloudFormation template.
-//     * This is provided to facilitate communication between the custom resource provider and the template developer.
-//     */
-//    #[serde(rename = "LogicalResourceId")]
-//    pub logical_resource_id: String,
-//
-//    /*
-//     * A required custom resource provider-defined physical ID that is unique for that provider.
-//     * Required: Always sent with Update and Delete requests; never sent with Create.
-//     */
-//    #[serde(rename = "PhysicalResourceId")]
-//    pub physical_resource_id: Option<String>,
-//
-//    /*
-//     * This field contains the contents of the Properties object sent by the template developer.
-//     * Its contents are defined by the custom resource provider.
-//     */
-//    #[serde(rename = "ResourceProperties")]
-//    pub resource_properties: Option<T>,
-//   
-//    /*
-//     * Used only for Update requests.
-//     * Contains the resource properties that were declared previous to the update request.
-//     */
-//    #[serde(rename = "OldResourceProperties")]
-//    pub old_resource_properties: Option<T>,
-//}
+/*
+ * Any of the request events, deserialized in one pass by its RequestType.
+ * Each variant holds the existing event struct, so it can be matched exhaustively and the
+ * events unwrapped for code written against the individual structs.
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "RequestType")]
+pub enum ProviderRequestEvent<C, U, D, O = U> {
+    #[serde(rename = "Create")]
+    Create(ProviderRequestCreateEvent<C>),
+    #[serde(rename = "Update")]
+    Update(ProviderRequestUpdateEvent<U, O>),
+    #[serde(rename = "Delete")]
+    Delete(ProviderRequestDeleteEvent<D>),
+}
+
+// serialized as the wrapped event, which carries the RequestType itself
+impl <C, U, D, O> serde::Serialize for ProviderRequestEvent<C, U, D, O>
+where C: serde::Serialize,
+      U: serde::Serialize,
+      D: serde::Serialize,
+      O: serde::Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        match self {
+            ProviderRequestEvent::Create(event) => event.serialize(serializer),
+            ProviderRequestEvent::Update(event) => event.serialize(serializer),
+            ProviderRequestEvent::Delete(event) => event.serialize(serializer),
+        }
+    }
+}
+
+impl <C, U, D, O> ProviderRequestEvent<C, U, D, O> {
+    fn details(&self) -> &dyn ProviderRequestEventDetails {
+        match self {
+            ProviderRequestEvent::Create(event) => event,
+            ProviderRequestEvent::Update(event) => event,
+            ProviderRequestEvent::Delete(event) => event,
+        }
+    }
+}
+
+impl <C, U, D, O> ProviderRequestEventDetails for ProviderRequestEvent<C, U, D, O> {
+    fn request_type(&self) -> RequestType { self.details().request_type() }
+    fn response_url(&self) -> String { self.details().response_url() }
+    fn stack_id(&self) -> String { self.details().stack_id() }
+    fn request_id(&self) -> String { self.details().request_id() }
+    fn resource_type(&self) -> String { self.details().resource_type() }
+    fn logical_resource_id(&self) -> String { self.details().logical_resource_id() }
+    fn physical_resource_id(&self) -> Option<String> { self.details().physical_resource_id() }
+}
+
+impl <C, U, D, O> From<ProviderRequestCreateEvent<C>> for ProviderRequestEvent<C, U, D, O> {
+    fn from(event: ProviderRequestCreateEvent<C>) -> Self { ProviderRequestEvent::Create(event) }
+}
+
+impl <C, U, D, O> From<ProviderRequestUpdateEvent<U, O>> for ProviderRequestEvent<C, U, D, O> {
+    fn from(event: ProviderRequestUpdateEvent<U, O>) -> Self { ProviderRequestEvent::Update(event) }
+}
+
+impl <C, U, D, O> From<ProviderRequestDeleteEvent<D>> for ProviderRequestEvent<C, U, D, O> {
+    fn from(event: ProviderRequestDeleteEvent<D>) -> Self { ProviderRequestEvent::Delete(event) }
+}
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum ResponseStatus {
@@ -329,14 +459,16 @@ pub struct ProviderResponseBuilder {
     logical_resource_id: String,
     no_echo: bool,
     data: Option<Value>,
+    oversize: Vec<OversizeStrategy>,
 }
 
 impl ProviderResponseBuilder {
     pub fn from_event_ref <T> (event:&T) -> Self where T: ProviderRequestEventDetails {
-        let physical_resource_id = build_physical_resource_id(
+        // Update and Delete keep the id the resource already has, only Create generates one
+        let physical_resource_id = event.physical_resource_id().unwrap_or_else(|| build_physical_resource_id(
             event.stack_id().clone(),
             event.logical_resource_id().clone(),
-        );
+        ));
  
         ProviderResponseBuilder {
             status: ResponseStatus::Failed,
@@ -347,13 +479,15 @@ impl ProviderResponseBuilder {
             logical_resource_id: event.logical_resource_id().clone(),
             no_echo: false,
             data: None as Option<Value>,
+            oversize: Vec::new(),
         }
     }
     pub fn from_event <T> (event:T) -> Self where T: ProviderRequestEventDetails {
-        let physical_resource_id = build_physical_resource_id(
+        // Update and Delete keep the id the resource already has, only Create generates one
+        let physical_resource_id = event.physical_resource_id().unwrap_or_else(|| build_physical_resource_id(
             event.stack_id(),
             event.logical_resource_id(),
-        );
+        ));
  
         ProviderResponseBuilder {
             status: ResponseStatus::Failed,
@@ -364,9 +498,40 @@ impl ProviderResponseBuilder {
             logical_resource_id: event.logical_resource_id(),
             no_echo: false,
             data: None as Option<Value>,
+            oversize: Vec::new(),
         }
     }
-     
+    /*
+     * Builds from a raw request event that could not be deserialized into one of the typed events,
+     * copying whatever identifying fields are present so a FAILED response can still be written.
+     * Missing fields are left empty; an incoming PhysicalResourceId is echoed back unchanged.
+     */
+    pub fn from_value(event: &Value) -> Self {
+        let field = |name: &str| event.get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let stack_id = field("StackId");
+        let logical_resource_id = field("LogicalResourceId");
+        let physical_resource_id = event.get("PhysicalResourceId")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| build_physical_resource_id(stack_id.clone(), logical_resource_id.clone()));
+
+        ProviderResponseBuilder {
+            status: ResponseStatus::Failed,
+            reason: "reason not given".to_string(),
+            physical_resource_id,
+            stack_id,
+            request_id: field("RequestId"),
+            logical_resource_id,
+            no_echo: false,
+            data: None as Option<Value>,
+            oversize: Vec::new(),
+        }
+    }
+
     pub fn status(mut self, status: ResponseStatus) -> ProviderResponseBuilder {
         self.status = status;
         self
@@ -379,6 +544,12 @@ impl ProviderResponseBuilder {
         self.physical_resource_id = physical_resource_id;
         self
     }
+    // a PhysicalResourceId generated by the strategy, e.g. a new id for an Update that replaces the resource
+    pub fn physical_id_from<S>(mut self, strategy: &S, event: &dyn ProviderRequestEventDetails) -> Result<ProviderResponseBuilder, PhysicalIdError>
+    where S: PhysicalIdStrategy + ?Sized {
+        self.physical_resource_id = strategy.physical_id(event)?;
+        Ok(self)
+    }
     pub fn stack_id(mut self, stack_id: String) -> ProviderResponseBuilder {
         self.stack_id = stack_id;
         self
@@ -401,6 +572,21 @@ impl ProviderResponseBuilder {
         self
     }
 
+    // what build_checked does with a response over MAX_RESPONSE_SIZE, strategies are tried in the order added
+    pub fn on_oversize(mut self, strategy: OversizeStrategy) -> ProviderResponseBuilder {
+        self.oversize.push(strategy);
+        self
+    }
+
+    /*
+     * Builds the response, measuring it as serialized against the 4 KB CloudFormation accepts.
+     * An oversized response is fitted with the strategies given to on_oversize, or rejected when they do not suffice.
+     */
+    pub fn build_checked (mut self) -> Result<ProviderResponse, ResponseTooLarge> {
+        let oversize = std::mem::take(&mut self.oversize);
+        self.build().fit(&oversize)
+    }
+
     pub fn build (self) -> ProviderResponse {
         ProviderResponse {
             status: self.status,
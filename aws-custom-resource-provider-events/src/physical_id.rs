@@ -0,0 +1,182 @@
+use crate::{build_physical_resource_id, ProviderRequestEventDetails, StackArnError};
+use std::error;
+use std::fmt;
+
+// CloudFormation accepts a PhysicalResourceId of up to 1 KB
+pub const MAX_PHYSICAL_RESOURCE_ID_SIZE: usize = 1024;
+
+/*
+ * How a provider names the resources it creates. A Create answered with the default StackHash is given the id
+ * the strategy generates instead, and an Update answered with a different id tells CloudFormation the resource
+ * was replaced, after which it sends a Delete for the old id. A provider may also set the id of a response
+ * itself, with ProviderResponseBuilder::physical_id_from.
+ */
+pub trait PhysicalIdStrategy: Send + Sync {
+    /// a physical id for the resource of the event, whether it is being created or replaced
+    fn generate(&self, event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError>;
+
+    /// the generated id, checked to be a non-empty string of at most MAX_PHYSICAL_RESOURCE_ID_SIZE bytes
+    fn physical_id(&self, event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError> {
+        let physical_id = self.generate(event)?;
+        validate(&physical_id)?;
+        Ok(physical_id)
+    }
+}
+
+/// whether CloudFormation accepts the id, a non-empty string of at most MAX_PHYSICAL_RESOURCE_ID_SIZE bytes
+pub fn validate(physical_id: &str) -> Result<(), PhysicalIdError> {
+    match physical_id.len() {
+        0 => Err(PhysicalIdError::Empty()),
+        size if size > MAX_PHYSICAL_RESOURCE_ID_SIZE => Err(PhysicalIdError::TooLong(size)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum PhysicalIdError {
+    /// the strategy produced an empty id
+    Empty(),
+
+    /// the strategy produced an id of the given size in bytes, over MAX_PHYSICAL_RESOURCE_ID_SIZE
+    TooLong(usize),
+
+    /// the template names a placeholder that is not supported
+    UnknownPlaceholder(String),
+
+    /// the template needs a part of the StackId, which could not be parsed
+    InvalidStackArn(StackArnError),
+
+    /// no random bytes were available to generate a UUID
+    RandomUnavailable(),
+}
+
+impl fmt::Debug for PhysicalIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty() => write!(f, "the PhysicalResourceId is empty"),
+            Self::TooLong(size) =>
+                write!(f, "the PhysicalResourceId is {} bytes, over the {} byte limit", size, MAX_PHYSICAL_RESOURCE_ID_SIZE),
+            Self::UnknownPlaceholder(name) => write!(f, "unknown placeholder {{{}}} in the PhysicalResourceId template", name),
+            Self::InvalidStackArn(arn_err) => write!(f, "invalid StackId: {}", arn_err),
+            Self::RandomUnavailable() => write!(f, "no random bytes available for the PhysicalResourceId"),
+        }
+    }
+}
+
+impl fmt::Display for PhysicalIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self, f)
+    }
+}
+
+impl error::Error for PhysicalIdError {
+    fn description(&self) -> &str {
+        match *self {
+            Self::Empty() => "empty PhysicalResourceId",
+            Self::TooLong(_) => "PhysicalResourceId over the size limit",
+            Self::UnknownPlaceholder(_) => "unknown placeholder in the PhysicalResourceId template",
+            Self::InvalidStackArn(_) => "StackId needed by the PhysicalResourceId template is invalid",
+            Self::RandomUnavailable() => "no random bytes available",
+        }
+    }
+}
+
+/// The SHA-256 of the StackId and LogicalResourceId, the same for every request of the resource.
+#[derive(Clone, Debug, Default)]
+pub struct StackHash;
+
+impl PhysicalIdStrategy for StackHash {
+    fn generate(&self, event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError> {
+        Ok(build_physical_resource_id(event.stack_id(), event.logical_resource_id()))
+    }
+}
+
+/// The StackHash with a generation counter. A Create is given the plain StackHash, generation 0, and each
+/// replacement increments the generation of the current id, `<hash>-<generation>`, so the id changes while
+/// staying predictable.
+#[derive(Clone, Debug, Default)]
+pub struct GenerationalHash;
+
+impl PhysicalIdStrategy for GenerationalHash {
+    fn generate(&self, event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError> {
+        let hash = build_physical_resource_id(event.stack_id(), event.logical_resource_id());
+        let current = match event.physical_resource_id() {
+            Some(current) => current,
+            None => return Ok(hash),
+        };
+        let generation = current.strip_prefix(&hash)
+            .and_then(|suffix| suffix.strip_prefix('-'))
+            .and_then(|generation| generation.parse::<u64>().ok())
+            .unwrap_or(0);
+        Ok(format!("{}-{}", hash, generation + 1))
+    }
+}
+
+/// A random (version 4) UUID, different for every call.
+#[derive(Clone, Debug, Default)]
+pub struct RandomUuid;
+
+impl PhysicalIdStrategy for RandomUuid {
+    fn generate(&self, _event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError> {
+        use ring::rand::SecureRandom;
+        let mut bytes = [0u8; 16];
+        ring::rand::SystemRandom::new().fill(&mut bytes)
+            .map_err(|_| PhysicalIdError::RandomUnavailable())?;
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+    }
+}
+
+/// An id the provider already has for the resource, such as an ARN or a parameter name. Meant for
+/// ProviderResponseBuilder::physical_id_from once the resource exists, as the strategy of a provider it would
+/// give every resource the same id.
+#[derive(Clone, Debug)]
+pub struct NaturalId(pub String);
+
+impl PhysicalIdStrategy for NaturalId {
+    fn generate(&self, _event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError> {
+        Ok(self.0.clone())
+    }
+}
+
+/*
+ * An id formatted from the event, e.g. `{StackName}-{LogicalResourceId}`. The placeholders are
+ * {StackName}, {StackUuid}, {Partition}, {Region}, {AccountId} taken from the StackId, and
+ * {LogicalResourceId}, {RequestId}, {ResourceType} and {Hash}, the StackHash of the resource.
+ */
+#[derive(Clone, Debug)]
+pub struct Template(pub String);
+
+impl PhysicalIdStrategy for Template {
+    fn generate(&self, event: &dyn ProviderRequestEventDetails) -> Result<String, PhysicalIdError> {
+        let mut physical_id = String::new();
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            physical_id.push_str(&rest[..start]);
+            let placeholder = &rest[start + 1..end];
+            let stack_arn = || event.stack_arn().map_err(PhysicalIdError::InvalidStackArn);
+            let value = match placeholder {
+                "StackName" => stack_arn()?.stack_name().to_string(),
+                "StackUuid" => stack_arn()?.stack_uuid().to_string(),
+                "Partition" => stack_arn()?.partition().to_string(),
+                "Region" => stack_arn()?.region().to_string(),
+                "AccountId" => stack_arn()?.account_id().to_string(),
+                "LogicalResourceId" => event.logical_resource_id(),
+                "RequestId" => event.request_id(),
+                "ResourceType" => event.resource_type(),
+                "Hash" => build_physical_resource_id(event.stack_id(), event.logical_resource_id()),
+                _ => return Err(PhysicalIdError::UnknownPlaceholder(placeholder.to_string())),
+            };
+            physical_id.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        physical_id.push_str(rest);
+        Ok(physical_id)
+    }
+}
@@ -0,0 +1,161 @@
+use serde::de::{ self, Deserialize, Deserializer, IgnoredAny, IntoDeserializer, MapAccess, Visitor };
+use std::fmt;
+
+// properties of the AWS::CloudFormation::CustomResource itself, which CloudFormation copies into
+// ResourceProperties alongside the provider-defined ones. They are modelled on the events directly.
+pub const SERVICE_PROPERTIES: [&str; 2] = ["ServiceToken", "ServiceTimeout"];
+
+/*
+ * Deserialize ResourceProperties without the service properties, so provider property types can use
+ * #[serde(deny_unknown_fields)]. The keys are skipped as the map is read rather than removed from a
+ * copy, which keeps the original deserializer, and any path it tracks, for the remaining fields.
+ */
+pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where D: Deserializer<'de>,
+      T: Deserialize<'de>,
+{
+    Option::<WithoutServiceProperties<T>>::deserialize(deserializer)
+        .map(|properties| properties.map(|p| p.0))
+}
+
+struct WithoutServiceProperties<T>(T);
+
+impl <'de, T> Deserialize<'de> for WithoutServiceProperties<T>
+where T: Deserialize<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        T::deserialize(Filtered(deserializer)).map(WithoutServiceProperties)
+    }
+}
+
+// a deserializer that hands every map it visits to the visitor with the service properties skipped
+struct Filtered<D>(D);
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {$(
+        fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> where V: Visitor<'de> {
+            self.0.$method($($arg,)* Filtering(visitor))
+        }
+    )*}
+}
+
+impl <'de, D> Deserializer<'de> for Filtered<D>
+where D: Deserializer<'de> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+struct Filtering<V>(V);
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty);)*) => {$(
+        fn $method<E>(self, v: $ty) -> Result<Self::Value, E> where E: de::Error {
+            self.0.$method(v)
+        }
+    )*}
+}
+
+impl <'de, V> Visitor<'de> for Filtering<V>
+where V: Visitor<'de> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool);
+        visit_i64(i64);
+        visit_i128(i128);
+        visit_u64(u64);
+        visit_u128(u128);
+        visit_f64(f64);
+        visit_char(char);
+        visit_str(&str);
+        visit_borrowed_str(&'de str);
+        visit_string(String);
+        visit_bytes(&[u8]);
+        visit_borrowed_bytes(&'de [u8]);
+        visit_byte_buf(Vec<u8>);
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> where E: de::Error {
+        self.0.visit_none()
+    }
+    fn visit_unit<E>(self) -> Result<Self::Value, E> where E: de::Error {
+        self.0.visit_unit()
+    }
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error> where D: Deserializer<'de> {
+        self.0.visit_some(deserializer)
+    }
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error> where D: Deserializer<'de> {
+        self.0.visit_newtype_struct(deserializer)
+    }
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error> where A: de::SeqAccess<'de> {
+        self.0.visit_seq(seq)
+    }
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error> where A: de::EnumAccess<'de> {
+        self.0.visit_enum(data)
+    }
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error> where A: MapAccess<'de> {
+        self.0.visit_map(FilteredMap(map))
+    }
+}
+
+struct FilteredMap<A>(A);
+
+impl <'de, A> MapAccess<'de> for FilteredMap<A>
+where A: MapAccess<'de> {
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> where K: de::DeserializeSeed<'de> {
+        while let Some(key) = self.0.next_key::<String>()? {
+            if SERVICE_PROPERTIES.contains(&key.as_str()) {
+                self.0.next_value::<IgnoredAny>()?;
+                continue;
+            }
+            return seed.deserialize(key.into_deserializer()).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error> where V: de::DeserializeSeed<'de> {
+        self.0.next_value_seed(seed)
+    }
+}
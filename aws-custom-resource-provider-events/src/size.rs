@@ -0,0 +1,197 @@
+use crate::{ProviderResponse, ResponseStatus};
+use std::error;
+use std::fmt;
+
+// CloudFormation rejects a response whose serialized JSON is larger than this
+pub const MAX_RESPONSE_SIZE: usize = 4096;
+
+// how many of the largest Data keys are named when a response is too large
+const LARGEST_KEYS_REPORTED: usize = 5;
+
+const TRUNCATED: &str = "...";
+
+/*
+ * What to do with a response larger than MAX_RESPONSE_SIZE. Strategies are applied in the order given,
+ * until the response fits; when none make it fit, the response is rejected with ResponseTooLarge.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum OversizeStrategy {
+    /// shorten the Reason, e.g. a long error chain, marking where it was cut
+    TruncateReason,
+
+    /// remove the given Data keys, lowest priority first, until the response fits
+    DropKeys(Vec<String>),
+
+    /// remove Data altogether, e.g. from the response to a Delete, whose Data CloudFormation never reads
+    DropData,
+
+    /// replace the response with a FAILED response, without Data, whose Reason names the largest Data keys
+    Fail,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ResponseTooLarge {
+    /* the serialized size of the response, in bytes */
+    pub size: usize,
+    /* the largest Data keys, with the serialized size of each, largest first */
+    pub largest_keys: Vec<(String, usize)>,
+}
+
+impl fmt::Debug for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Response of {} bytes exceeds the {} byte limit", self.size, MAX_RESPONSE_SIZE)?;
+        if !self.largest_keys.is_empty() {
+            let keys = self.largest_keys.iter()
+                .map(|(key, size)| format!("{} ({} bytes)", key, size))
+                .collect::<Vec<_>>();
+            write!(f, ", largest Data keys: {}", keys.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self, f)
+    }
+}
+
+impl error::Error for ResponseTooLarge {
+    fn description(&self) -> &str {
+        "response exceeds the CloudFormation size limit"
+    }
+}
+
+impl ProviderResponse {
+    /// the size of the response as written to the ResponseURL, in bytes
+    pub fn serialized_size(&self) -> usize {
+        serde_json::to_vec(self).map(|json| json.len()).unwrap_or(usize::MAX)
+    }
+
+    /// the response when it is within MAX_RESPONSE_SIZE, after applying the strategies in turn if it is not
+    pub fn fit(self, strategies: &[OversizeStrategy]) -> Result<ProviderResponse, ResponseTooLarge> {
+        let size = self.serialized_size();
+        if size <= MAX_RESPONSE_SIZE {
+            return Ok(self);
+        }
+        let too_large = ResponseTooLarge { size, largest_keys: self.largest_keys() };
+
+        let mut response = self;
+        for strategy in strategies {
+            response = match strategy {
+                OversizeStrategy::TruncateReason => response.truncate_reason(),
+                OversizeStrategy::DropKeys(keys) => response.drop_keys(keys),
+                OversizeStrategy::DropData => ProviderResponse { data: None, ..response },
+                OversizeStrategy::Fail => ProviderResponse {
+                    status: ResponseStatus::Failed,
+                    reason: too_large.to_string(),
+                    data: None,
+                    ..response
+                }.truncate_reason(),
+            };
+            if response.serialized_size() <= MAX_RESPONSE_SIZE {
+                return Ok(response);
+            }
+        }
+        Err(too_large)
+    }
+
+    fn largest_keys(&self) -> Vec<(String, usize)> {
+        let mut keys = match &self.data {
+            Some(serde_json::Value::Object(data)) => data.iter()
+                .map(|(key, value)| (key.clone(), value.to_string().len()))
+                .collect::<Vec<_>>(),
+            _ => Vec::new(),
+        };
+        keys.sort_by(|(_, a), (_, b)| b.cmp(a));
+        keys.truncate(LARGEST_KEYS_REPORTED);
+        keys
+    }
+
+    fn truncate_reason(mut self) -> ProviderResponse {
+        if self.serialized_size() <= MAX_RESPONSE_SIZE {
+            return self;
+        }
+        // when the rest of the response is too large by itself, e.g. its Data, the Reason is left for a later strategy
+        let reason = std::mem::replace(&mut self.reason, TRUNCATED.to_string());
+        if self.serialized_size() > MAX_RESPONSE_SIZE {
+            self.reason = reason;
+            return self;
+        }
+        // escaping makes the serialized Reason longer than the Reason itself, search for the longest prefix that fits
+        let (mut fits, mut too_long) = (0, reason.len());
+        while too_long - fits > 1 {
+            let mut keep = (fits + too_long) / 2;
+            while !reason.is_char_boundary(keep) {
+                keep -= 1;
+            }
+            self.reason = format!("{}{}", &reason[..keep], TRUNCATED);
+            match self.serialized_size() <= MAX_RESPONSE_SIZE {
+                true if keep > fits => fits = keep,
+                true => break,
+                false => too_long = keep,
+            }
+        }
+        self.reason = format!("{}{}", &reason[..fits], TRUNCATED);
+        self
+    }
+
+    fn drop_keys(mut self, keys: &[String]) -> ProviderResponse {
+        for key in keys {
+            if self.serialized_size() <= MAX_RESPONSE_SIZE {
+                break;
+            }
+            if let Some(serde_json::Value::Object(data)) = &mut self.data {
+                data.remove(key);
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProviderResponseBuilder;
+    use serde_json::json;
+
+    fn response(status: ResponseStatus, reason: &str, data: serde_json::Value) -> ProviderResponse {
+        ProviderResponseBuilder::from_value(&json!({ "StackId": "stack", "LogicalResourceId": "Resource", "RequestId": "request" }))
+            .status(status)
+            .reason(reason.to_string())
+            .data(data)
+            .build()
+    }
+
+    #[test]
+    fn a_reason_too_long_is_truncated() {
+        let reason = format!("Unable to delete bucket: {}", "BucketNotEmpty ".repeat(500));
+        let fitted = response(ResponseStatus::Failed, &reason, json!({})).fit(&[OversizeStrategy::TruncateReason]).unwrap();
+        assert!(fitted.serialized_size() <= MAX_RESPONSE_SIZE);
+        assert!(fitted.reason.starts_with("Unable to delete bucket: BucketNotEmpty"));
+        assert!(fitted.reason.ends_with(TRUNCATED));
+        assert!(fitted.reason.len() > MAX_RESPONSE_SIZE / 2);
+    }
+
+    #[test]
+    fn the_reason_is_kept_when_data_is_too_large() {
+        let reason = "Unable to delete bucket: BucketNotEmpty";
+        let data = json!({ "Large": "x".repeat(5 * 1024), "Small": "y" });
+        let strategies = [OversizeStrategy::TruncateReason, OversizeStrategy::DropData];
+        let fitted = response(ResponseStatus::Failed, reason, data.clone()).fit(&strategies).unwrap();
+        assert_eq!(fitted.reason, reason);
+        assert_eq!(fitted.data, None);
+
+        let strategies = [OversizeStrategy::TruncateReason, OversizeStrategy::DropKeys(vec!["Large".to_string()])];
+        let fitted = response(ResponseStatus::Success, "created", data).fit(&strategies).unwrap();
+        assert_eq!(fitted.reason, "created");
+        assert_eq!(fitted.data, Some(json!({ "Small": "y" })));
+    }
+
+    #[test]
+    fn a_response_that_cannot_fit_is_rejected() {
+        let data = json!({ "Large": "x".repeat(5 * 1024) });
+        let too_large = response(ResponseStatus::Success, "created", data).fit(&[OversizeStrategy::TruncateReason]).unwrap_err();
+        assert_eq!(too_large.largest_keys, vec![("Large".to_string(), 5 * 1024 + 2)]);
+    }
+}
@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 
 use aws_custom_resource_provider_events::{
+    coerce,
     ResponseStatus,
     ProviderResponse,
     ProviderResponseBuilder,
@@ -11,7 +12,10 @@ use aws_custom_resource_provider_lambda::{
     types::CreateEvent,
     types::UpdateEvent,
     types::DeleteEvent,
-    types::Provider,
+    types::FallibleProvider,
+    context::ProviderContext,
+    layers::LoggingLayer,
+    layers::TimingLayer,
 };
 use lambda_runtime::{Error as LambdaError};
 use serde_derive::{ Serialize, Deserialize };
@@ -20,6 +24,8 @@ use aws_sdk_ssm as ssm;
 use service_fn::service_fn;
 
 use tracing::info;
+use std::error;
+use std::fmt;
 
 //--- Resource properties specific to our custom-resource implementation
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -27,6 +33,8 @@ pub struct CreateResourceProperties {
     pub ssm_name: String,
     pub ssm_desc: String,
     pub ssm_value: String,
+    #[serde(default, with = "coerce::option_from_str")]
+    pub ssm_overwrite: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -34,6 +42,8 @@ pub struct UpdateResourceProperties {
     pub ssm_name: String,
     pub ssm_desc: String,
     pub ssm_value: String,
+    #[serde(default, with = "coerce::option_from_str")]
+    pub ssm_overwrite: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -61,128 +71,113 @@ impl ArnProvider {
     }
 }
 
+#[derive(Debug)]
+pub enum ArnProviderError {
+    MissingProperties,
+    PutParameter(ssm::types::SdkError<ssm::error::PutParameterError>),
+    DeleteParameter(ssm::types::SdkError<ssm::error::DeleteParameterError>),
+}
+
+impl fmt::Display for ArnProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingProperties => write!(f, "Missing or invalid 'ResourceProperties' parameter"),
+            Self::PutParameter(_) => write!(f, "error while attemting to call ssm::put_parameter"),
+            Self::DeleteParameter(_) => write!(f, "error while attemting to call ssm::delete_parameter"),
+        }
+    }
+}
+
+impl error::Error for ArnProviderError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::MissingProperties => None,
+            Self::PutParameter(sdk_err) => Some(sdk_err),
+            Self::DeleteParameter(sdk_err) => Some(sdk_err),
+        }
+    }
+}
+
 #[async_trait]
-impl Provider for ArnProvider {
+impl FallibleProvider for ArnProvider {
     type Create = CreateEvent<CreateResourceProperties>;
     type Update = UpdateEvent<UpdateResourceProperties>;
     type Delete = DeleteEvent<DeleteResourceProperties>;
+    type Error = ArnProviderError;
 
-    async fn create(&self, create_event: Self::Create) -> ProviderResponse {
+    async fn create(&self, create_event: Self::Create, _context: &ProviderContext) -> Result<ProviderResponse, Self::Error> {
         info!("create event started");
         let event = create_event.0;
- 
-        if event.resource_properties.is_none() {
-            return ProviderResponseBuilder::from_event(event)
-                .status(ResponseStatus::Failed)
-                .reason("Missing or invalid 'ResourceProperties' parameter".to_string())
-                .build()
-        }
-        let props = event.resource_properties.as_ref().unwrap();
+        let props = event.resource_properties.as_ref()
+            .ok_or(ArnProviderError::MissingProperties)?;
 
-        let resp_res = self.config.ssm
+        let resp = self.config.ssm
             .put_parameter()
-            .overwrite(true)
+            .overwrite(props.ssm_overwrite.unwrap_or(true))
             .r#type(ssm::model::ParameterType::String)
             .name(props.ssm_name.clone())
             .value(props.ssm_value.clone())
             .description(props.ssm_desc.clone())
             .send()
-            .await;
-        if resp_res.is_err() {
-            let aws_sdk_err = resp_res.unwrap_err();
-            return ProviderResponseBuilder::from_event(event)
-                .status(ResponseStatus::Failed)
-                .reason(format!("Create error while attemting to call ssm::put_parameter: {:?}", aws_sdk_err))
-                .build()
-        }
- 
-        let resp = resp_res.unwrap();
+            .await
+            .map_err(ArnProviderError::PutParameter)?;
 
         let data = vec![("ssm_param_ver", Value::from(resp.version()))]
             .into_iter()
             .collect::<Value>();
 
-        ProviderResponseBuilder::from_event(event)
+        Ok(ProviderResponseBuilder::from_event(event)
             .status(ResponseStatus::Success)
             .reason("Ok".to_string())
             .data(data)
-            .build()
+            .build())
     }
 
-    async fn update(&self, update_event: Self::Update) -> ProviderResponse {
+    async fn update(&self, update_event: Self::Update, _context: &ProviderContext) -> Result<ProviderResponse, Self::Error> {
         info!("update event started");
         let event = update_event.0;
+        let props = event.resource_properties.as_ref()
+            .ok_or(ArnProviderError::MissingProperties)?;
 
-        if event.resource_properties.is_none() {
-            return ProviderResponseBuilder::from_event(event)
-                .status(ResponseStatus::Failed)
-                .reason("Missing or invalid 'ResourceProperties' parameter".to_string())
-                .build()
-        }
-        let props = event.resource_properties.as_ref().unwrap();
-
-        let resp_res = self.config.ssm
+        let resp = self.config.ssm
             .put_parameter()
-            .overwrite(true)
+            .overwrite(props.ssm_overwrite.unwrap_or(true))
             .r#type(ssm::model::ParameterType::String)
             .name(props.ssm_name.clone())
             .value(props.ssm_value.clone())
             .description(props.ssm_desc.clone())
             .send()
-            .await;
-        if resp_res.is_err() {
-            let aws_sdk_err = resp_res.unwrap_err();
-            return ProviderResponseBuilder::from_event(event)
-                .status(ResponseStatus::Failed)
-                .reason(format!("Update error while attemting to call ssm::put_parameter: {:?}", aws_sdk_err))
-                .build()
-        }
-
-        let resp = resp_res.unwrap();
+            .await
+            .map_err(ArnProviderError::PutParameter)?;
 
         let data = vec![("ssm_param_ver", Value::from(resp.version()))]
             .into_iter()
             .collect::<Value>();
 
-        ProviderResponseBuilder::from_event(event)
+        Ok(ProviderResponseBuilder::from_event(event)
             .status(ResponseStatus::Success)
             .reason("Ok".to_string())
             .data(data)
-            .build()
+            .build())
     }
 
-    async fn delete(&self, delete_event: Self::Delete) -> ProviderResponse {
+    async fn delete(&self, delete_event: Self::Delete, _context: &ProviderContext) -> Result<ProviderResponse, Self::Error> {
         info!("delete event started");
         let event = delete_event.0;
+        let props = event.resource_properties.as_ref()
+            .ok_or(ArnProviderError::MissingProperties)?;
 
-        if event.resource_properties.is_none() {
-            return ProviderResponseBuilder::from_event(event)
-                .status(ResponseStatus::Failed)
-                .reason("Missing or invalid 'ResourceProperties' parameter".to_string())
-                .build()
-        }
-        let props = event.resource_properties.as_ref().unwrap();
-
-        let resp_res = self.config.ssm
+        self.config.ssm
             .delete_parameter()
             .name(props.ssm_name.clone())
             .send()
-            .await;
-
-        if resp_res.is_err() {
-            let aws_sdk_err = resp_res.unwrap_err();
-            return ProviderResponseBuilder::from_event(event)
-                .status(ResponseStatus::Failed)
-                .reason(format!("Delete error while attemting to call ssm::delete_parameter: {:?}", aws_sdk_err))
-                .build()
-        }
-
-        let _resp = resp_res.unwrap();
+            .await
+            .map_err(ArnProviderError::DeleteParameter)?;
 
-        ProviderResponseBuilder::from_event(event)
+        Ok(ProviderResponseBuilder::from_event(event)
             .status(ResponseStatus::Success)
             .reason("Ok".to_string())
-            .build()
+            .build())
     }
 }
 
@@ -205,7 +200,9 @@ async fn main() -> Result<(), LambdaError> {
     };
 
     // config aws-custom-resource-provider
-    let handler_config = HandlerConfig::new(ArnProvider::new(provider_config));
+    let handler_config = HandlerConfig::new(ArnProvider::new(provider_config))
+        .with_layer(LoggingLayer)
+        .with_layer(TimingLayer);
 
     lambda_runtime::run(service_fn(handler_config, custom_resource_handler)).await?;
 
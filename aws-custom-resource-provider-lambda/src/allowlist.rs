@@ -0,0 +1,153 @@
+use crate::response_url::ResponseUrl;
+use hyper::Uri;
+
+// CloudFormation responses are written to a bucket per region, e.g. cloudformation-custom-resource-response-useast1
+const BUCKET_PREFIX: &str = "cloudformation-custom-resource-response-";
+
+/*
+ * Where the handler may write a response. The provider output, including Data, goes wherever the
+ * ResponseURL of the event points, so anyone able to invoke the function could otherwise redirect it.
+ *
+ * By default only https URLs of the CloudFormation response bucket of the function's own region are allowed,
+ * in the virtual-hosted or path style, on the global or regional S3 endpoints. Custom endpoints, such as a
+ * local S3 in tests, are allowed by scheme and authority.
+ */
+#[derive(Clone, Debug)]
+pub struct ResponseUrlAllowlist {
+    /* allow the regional CloudFormation response buckets */
+    cloudformation: bool,
+    /* additional endpoints allowed, matched on scheme and authority */
+    endpoints: Vec<Uri>,
+}
+
+impl Default for ResponseUrlAllowlist {
+    fn default() -> Self {
+        ResponseUrlAllowlist::cloudformation()
+    }
+}
+
+impl ResponseUrlAllowlist {
+    // only the CloudFormation response buckets
+    pub fn cloudformation() -> Self {
+        ResponseUrlAllowlist { cloudformation: true, endpoints: Vec::new() }
+    }
+
+    // nothing but the endpoints added with with_endpoint
+    pub fn endpoints_only() -> Self {
+        ResponseUrlAllowlist { cloudformation: false, endpoints: Vec::new() }
+    }
+
+    // also allow responses to the given endpoint, e.g. `http://localhost:4566`
+    pub fn with_endpoint(mut self, endpoint: Uri) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    /// whether a response may be written to the URL, by a function running in the given region when it is known
+    pub fn allows(&self, response_url: &ResponseUrl, region: Option<&str>) -> bool {
+        let uri = response_url.uri();
+        let endpoint = self.endpoints.iter().any(|endpoint|
+            endpoint.scheme() == uri.scheme() && endpoint.authority() == uri.authority());
+        endpoint || (self.cloudformation && is_cloudformation_bucket(uri, region).is_some())
+    }
+}
+
+fn is_cloudformation_bucket(uri: &Uri, region: Option<&str>) -> Option<()> {
+    if uri.scheme_str() != Some("https") || uri.port().is_some() {
+        return None;
+    }
+    let host = uri.host()?;
+
+    // virtual-hosted style: <bucket>.<endpoint>/<key>, path style: <endpoint>/<bucket>/<key>
+    let (bucket, endpoint) = match host.starts_with(BUCKET_PREFIX) {
+        true => host.split_once('.')?,
+        false => (uri.path().trim_start_matches('/').split('/').next()?, host),
+    };
+    let bucket_region = bucket.strip_prefix(BUCKET_PREFIX)
+        .filter(|r| !r.is_empty() && r.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()))?;
+
+    // the global endpoint, or the regional endpoint of the bucket's region
+    if endpoint != "s3.amazonaws.com" {
+        let endpoint_region = endpoint.strip_prefix("s3.").or_else(|| endpoint.strip_prefix("s3-"))
+            .and_then(|rest| rest.strip_suffix(".amazonaws.com").or_else(|| rest.strip_suffix(".amazonaws.com.cn")))?;
+        if endpoint_region.replace('-', "") != bucket_region {
+            return None;
+        }
+    }
+
+    // a function is only invoked by stacks in its own region
+    match region {
+        Some(region) if region.replace('-', "") != bucket_region => None,
+        _ => Some(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allows(allowlist: &ResponseUrlAllowlist, url: &str, region: Option<&str>) -> bool {
+        allowlist.allows(&url.parse().unwrap(), region)
+    }
+
+    #[test]
+    fn cloudformation_buckets_are_allowed() {
+        let allowlist = ResponseUrlAllowlist::default();
+        for (url, region) in [
+            ("https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com/key", Some("us-east-1")),
+            ("https://s3.amazonaws.com/cloudformation-custom-resource-response-useast1/key", Some("us-east-1")),
+            ("https://cloudformation-custom-resource-response-euwest1.s3.eu-west-1.amazonaws.com/key", Some("eu-west-1")),
+            ("https://s3.eu-west-1.amazonaws.com/cloudformation-custom-resource-response-euwest1/key", Some("eu-west-1")),
+            ("https://cloudformation-custom-resource-response-uswest2.s3-us-west-2.amazonaws.com/key", Some("us-west-2")),
+            ("https://s3-us-west-2.amazonaws.com/cloudformation-custom-resource-response-uswest2/key", Some("us-west-2")),
+            ("https://cloudformation-custom-resource-response-cnnorth1.s3.cn-north-1.amazonaws.com.cn/key", Some("cn-north-1")),
+            ("https://s3.cn-north-1.amazonaws.com.cn/cloudformation-custom-resource-response-cnnorth1/key", Some("cn-north-1")),
+            ("https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com/key", None),
+        ] {
+            assert!(allows(&allowlist, url, region), "{} {:?}", url, region);
+        }
+    }
+
+    #[test]
+    fn other_urls_are_refused() {
+        let allowlist = ResponseUrlAllowlist::default();
+        for (url, region) in [
+            // another region than the function's, or than the endpoint's
+            ("https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com/key", Some("eu-west-1")),
+            ("https://cloudformation-custom-resource-response-euwest1.s3.us-east-1.amazonaws.com/key", None),
+            ("https://s3.us-east-1.amazonaws.com/cloudformation-custom-resource-response-euwest1/key", None),
+            // hosts that only look like S3
+            ("https://cloudformation-custom-resource-response-useast1.evil.com/key", None),
+            ("https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com.evil.com/key", None),
+            ("https://s3.amazonaws.com.evil.com/cloudformation-custom-resource-response-useast1/key", None),
+            ("https://evil.com/cloudformation-custom-resource-response-useast1/key", None),
+            ("https://bucket.s3.amazonaws.com/cloudformation-custom-resource-response-useast1/key", None),
+            ("https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com@evil.com/key", None),
+            // other buckets
+            ("https://bucket.s3.amazonaws.com/key", None),
+            ("https://s3.amazonaws.com/bucket/key", None),
+            ("https://cloudformation-custom-resource-response-.s3.amazonaws.com/key", None),
+            // not https, or not the standard port
+            ("http://cloudformation-custom-resource-response-useast1.s3.amazonaws.com/key", None),
+            ("https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com:443/key", None),
+            ("https://s3.amazonaws.com:8443/cloudformation-custom-resource-response-useast1/key", None),
+        ] {
+            assert!(!allows(&allowlist, url, region), "{} {:?}", url, region);
+        }
+    }
+
+    #[test]
+    fn custom_endpoints_are_matched_on_scheme_and_authority() {
+        let endpoint = "http://localhost:4566".parse::<Uri>().unwrap();
+        let allowlist = ResponseUrlAllowlist::default().with_endpoint(endpoint.clone());
+        assert!(allows(&allowlist, "http://localhost:4566/bucket/key", Some("us-east-1")));
+        assert!(!allows(&allowlist, "https://localhost:4566/bucket/key", None));
+        assert!(!allows(&allowlist, "http://localhost:4567/bucket/key", None));
+        assert!(!allows(&allowlist, "http://localhost/bucket/key", None));
+        assert!(allows(&allowlist, "https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com/key", None));
+
+        let allowlist = ResponseUrlAllowlist::endpoints_only().with_endpoint(endpoint);
+        assert!(allows(&allowlist, "http://localhost:4566/bucket/key", None));
+        assert!(!allows(&allowlist, "https://cloudformation-custom-resource-response-useast1.s3.amazonaws.com/key", None));
+    }
+}
@@ -0,0 +1,98 @@
+use lambda_runtime::Context;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+/// The Lambda invocation context as seen by a Provider, with handler-level helpers.
+#[derive(Clone, Debug)]
+pub struct ProviderContext {
+    /// the invocation context handed to the handler by the Lambda runtime
+    pub lambda: Context,
+    /// time the handler reserves ahead of the deadline for writing the response
+    pub deadline_margin: Duration,
+    /// when the ServiceTimeout of the custom resource elapses, measured from when CloudFormation sent the request
+    pub service_deadline: Option<SystemTime>,
+}
+
+impl ProviderContext {
+    pub fn new(lambda: Context, deadline_margin: Duration) -> Self {
+        ProviderContext {
+            lambda,
+            deadline_margin,
+            service_deadline: None,
+        }
+    }
+
+    /// the ServiceTimeout runs from when CloudFormation sent the request, which the ResponseURL signing time records,
+    /// so time spent queued for the async invoke and in earlier attempts counts against it. now is only the fallback
+    pub fn with_service_timeout(mut self, service_timeout: Option<Duration>, sent_at: Option<SystemTime>) -> Self {
+        let now = SystemTime::now();
+        let started = sent_at.filter(|sent_at| *sent_at <= now).unwrap_or(now);
+        self.service_deadline = service_timeout.map(|timeout| started + timeout);
+        self
+    }
+
+    /// the AWS request id of the Lambda invocation
+    pub fn request_id(&self) -> &str {
+        &self.lambda.request_id
+    }
+
+    /// the ARN used to invoke the function, including any version or alias qualifier
+    pub fn invoked_function_arn(&self) -> &str {
+        &self.lambda.invoked_function_arn
+    }
+
+    /// the region, taken from the invoked function ARN
+    pub fn region(&self) -> Option<&str> {
+        self.arn_component(3)
+    }
+
+    /// the account id, taken from the invoked function ARN
+    pub fn account_id(&self) -> Option<&str> {
+        self.arn_component(4)
+    }
+
+    /// the CloudWatch log stream the invocation is writing to
+    pub fn log_stream_name(&self) -> &str {
+        &self.lambda.env_config.log_stream
+    }
+
+    /// the CloudWatch log group the invocation is writing to
+    pub fn log_group_name(&self) -> &str {
+        &self.lambda.env_config.log_group
+    }
+
+    /// when the Lambda will be stopped, None when the runtime did not supply a deadline
+    pub fn lambda_deadline(&self) -> Option<SystemTime> {
+        match self.lambda.deadline {
+            0 => None,
+            deadline => Some(UNIX_EPOCH + Duration::from_millis(deadline)),
+        }
+    }
+
+    /// the effective deadline: the earlier of the Lambda deadline and the ServiceTimeout of the resource
+    pub fn deadline(&self) -> Option<SystemTime> {
+        match (self.lambda_deadline(), self.service_deadline) {
+            (Some(lambda), Some(service)) => Some(lambda.min(service)),
+            (lambda, service) => lambda.or(service),
+        }
+    }
+
+    /// time left before the effective deadline
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline()
+            .map(|deadline| deadline.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    /// time left before the handler gives up on the provider and reports FAILED
+    pub fn time_budget(&self) -> Option<Duration> {
+        self.time_remaining()
+            .map(|remaining| remaining.saturating_sub(self.deadline_margin))
+    }
+
+    // arn:partition:lambda:region:account-id:function:name[:qualifier]
+    fn arn_component(&self, index: usize) -> Option<&str> {
+        self.lambda.invoked_function_arn
+            .split(':')
+            .nth(index)
+            .filter(|s| !s.is_empty())
+    }
+}
@@ -1,6 +1,7 @@
 use std::error;
 use std::fmt;
 use aws_custom_resource_provider_events::{ RequestType  };
+use crate::fields::{ self, FieldError };
 
 //#[derive(PartialEq)]
 pub enum Error {
@@ -10,25 +11,135 @@ pub enum Error {
     /// the ResponseURL paramater failed to parse into a valid  Uri value from the Hyper crate
     InvalidResponseURI(),
 
-    /// an event was received, with the given Request Type, but SerDe deserialization failed.
-    InvalidEvent(serde_json::Error, RequestType),
+    /// the ResponseURL, given redacted, is not on the allowlist of the handler, so nothing is written to it
+    DisallowedResponseURI(String),
+
+    /// the presigned ResponseURL expired at the given time, so no response can be written to it
+    ExpiredResponseURI(std::time::SystemTime),
+
+    /// an event was received, with the given Request Type, but SerDe deserialization failed at each of the given fields.
+    InvalidEvent(Vec<FieldError>, RequestType),
+
+    /// no route of the router matches the given ResourceType, the supported types are listed
+    UnsupportedResourceType(String, Vec<String>),
+
+    /// the provider did not produce a response within the given time budget ahead of the Lambda deadline
+    ProviderTimeout(std::time::Duration),
+
+    /// the idempotency store could not be read or written, with a description of the failure
+    IdempotencyStoreFailure(String),
+
+    /// a Data value could not be written to or read from the blob store, with a description of the failure
+    BlobStoreFailure(String),
+
+    /// the provider response could not be serialized into JSON
+    InvalidResponse(serde_json::Error),
+
+    /// the presigned ResponseURL rejected the response with a non-retryable status, and the given body
+    ResponseRejected(hyper::StatusCode, String),
+
+    /// the response could not be written after the given number of attempts, with a description of the last failure
+    ResponseUndeliverable(u32, String),
+}
+
+impl Error {
+    /// the error with anything taken from the given sensitive properties masked, for the Reason and the logs
+    pub fn redact(self, sensitive_properties: &[String]) -> Self {
+        match self {
+            Self::InvalidEvent(field_errors, event_request_type) =>
+                Self::InvalidEvent(fields::redact(field_errors, sensitive_properties), event_request_type),
+            other => other,
+        }
+    }
+
+    /// a human readable description of the error, suitable for the Reason field of a FAILED response.
+    pub fn reason(&self) -> String {
+        match self {
+            Self::InvalidRequestType(value) =>
+                format!("Unsupported RequestType {}, expected one of Create, Update, Delete",
+                    value.get("RequestType").map(|v| v.to_string()).unwrap_or("<missing>".to_string())),
+            Self::InvalidEvent(field_errors, event_request_type) =>
+                format!("Invalid {:?} event, {} offending field(s): {}", event_request_type, field_errors.len(),
+                    field_errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")),
+            Self::InvalidResponseURI() =>
+                "Invalid or missing ResponseURL".to_string(),
+            Self::DisallowedResponseURI(url) =>
+                format!("ResponseURL {} is not allowed", url),
+            Self::ExpiredResponseURI(expired_at) =>
+                format!("ResponseURL expired {} s ago", expired_at.elapsed().unwrap_or_default().as_secs()),
+            Self::UnsupportedResourceType(resource_type, supported) =>
+                format!("Unsupported ResourceType '{}', supported types: {}", resource_type, supported.join(", ")),
+            Self::ProviderTimeout(budget) =>
+                format!("Provider timed out after {} ms", budget.as_millis()),
+            Self::IdempotencyStoreFailure(failure) =>
+                format!("Idempotency store failure: {}", failure),
+            Self::BlobStoreFailure(failure) =>
+                format!("Blob store failure: {}", failure),
+            Self::InvalidResponse(serde_err) =>
+                format!("Unable to serialize the provider response: {}", serde_err),
+            Self::ResponseRejected(status, body) =>
+                format!("Response rejected with status {}: {}", status, body),
+            Self::ResponseUndeliverable(attempts, last_failure) =>
+                format!("Response undeliverable after {} attempts: {}", attempts, last_failure),
+        }
+    }
 }
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::InvalidRequestType(value) => 
+            // only the RequestType, the rest of the event may hold sensitive properties
+            Self::InvalidRequestType(value) =>
                 f.debug_struct("InvalidRequestType")
-                    .field("event", &format_args!("{}", serde_json::to_string(&value).unwrap_or("<event unavailable>".to_string())))
+                    .field("request_type", &format_args!("{}", value.get("RequestType").map(|v| v.to_string()).unwrap_or("<missing>".to_string())))
                     .finish(),
-            Self::InvalidEvent(serde_err, event_request_type) =>
-                f.debug_struct("InvalidCreateEvent")
-                    .field("serde_error", &format_args!("{:?}", serde_err))
+            Self::InvalidEvent(field_errors, event_request_type) =>
+                f.debug_struct("InvalidEvent")
+                    .field("field_errors", &format_args!("{:?}", field_errors))
                     .field("request_type", &format_args!("{:?}", event_request_type))
                     .finish(),
             Self::InvalidResponseURI() =>
                 f.debug_struct("InvalidResponseURI")
                     .finish(),
+            Self::DisallowedResponseURI(url) =>
+                f.debug_struct("DisallowedResponseURI")
+                    .field("url", &format_args!("{}", url))
+                    .finish(),
+            Self::ExpiredResponseURI(expired_at) =>
+                f.debug_struct("ExpiredResponseURI")
+                    .field("expired_at", &format_args!("{:?}", expired_at))
+                    .finish(),
+            Self::UnsupportedResourceType(resource_type, supported) =>
+                f.debug_struct("UnsupportedResourceType")
+                    .field("resource_type", &format_args!("{}", resource_type))
+                    .field("supported", &format_args!("{:?}", supported))
+                    .finish(),
+            Self::ProviderTimeout(budget) =>
+                f.debug_struct("ProviderTimeout")
+                    .field("budget_ms", &format_args!("{}", budget.as_millis()))
+                    .finish(),
+            Self::IdempotencyStoreFailure(failure) =>
+                f.debug_struct("IdempotencyStoreFailure")
+                    .field("failure", &format_args!("{}", failure))
+                    .finish(),
+            Self::BlobStoreFailure(failure) =>
+                f.debug_struct("BlobStoreFailure")
+                    .field("failure", &format_args!("{}", failure))
+                    .finish(),
+            Self::InvalidResponse(serde_err) =>
+                f.debug_struct("InvalidResponse")
+                    .field("serde_error", &format_args!("{:?}", serde_err))
+                    .finish(),
+            Self::ResponseRejected(status, body) =>
+                f.debug_struct("ResponseRejected")
+                    .field("status", &format_args!("{}", status))
+                    .field("body", &format_args!("{}", body))
+                    .finish(),
+            Self::ResponseUndeliverable(attempts, last_failure) =>
+                f.debug_struct("ResponseUndeliverable")
+                    .field("attempts", &format_args!("{}", attempts))
+                    .field("last_failure", &format_args!("{}", last_failure))
+                    .finish(),
         }
     }
 }
@@ -46,6 +157,15 @@ impl error::Error for Error {
             Self::InvalidRequestType(_) => "invalid or unknown RequestType",
             Self::InvalidEvent(_,_) => "invalid result from event deserialization attempt",
             Self::InvalidResponseURI() => "invalid or missing ResponseURI value in the event",
+            Self::DisallowedResponseURI(_) => "ResponseURI value in the event is not on the allowlist",
+            Self::ExpiredResponseURI(_) => "presigned ResponseURI value in the event has expired",
+            Self::UnsupportedResourceType(_,_) => "no provider is routed for the ResourceType",
+            Self::ProviderTimeout(_) => "provider did not respond before the Lambda deadline",
+            Self::IdempotencyStoreFailure(_) => "idempotency store could not be read or written",
+            Self::BlobStoreFailure(_) => "Data value could not be written to or read from the blob store",
+            Self::InvalidResponse(_) => "provider response could not be serialized",
+            Self::ResponseRejected(_,_) => "presigned ResponseURL rejected the response",
+            Self::ResponseUndeliverable(_,_) => "response could not be written to the presigned ResponseURL",
         }
     }
 }
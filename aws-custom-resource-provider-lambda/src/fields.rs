@@ -0,0 +1,170 @@
+use std::fmt;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+use crate::redact::REDACTED;
+
+// stop collecting after this many errors, the Reason of a FAILED response has to stay readable
+pub const MAX_FIELD_ERRORS: usize = 20;
+
+// placeholders tried in place of an offending field, so the fields after it are still checked.
+// None removes the field, which is all an unknown field needs, and all an optional one accepts.
+const PLACEHOLDERS: [Option<fn() -> Value>; 4] = [
+    Some(|| Value::String(String::new())),
+    Some(|| Value::from(0)),
+    Some(|| Value::Bool(false)),
+    None,
+];
+
+/// a field of the event that failed to deserialize, with its JSON path, e.g. `ResourceProperties.Rules[2].Port`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldError {
+    pub path: String,
+    pub message: String,
+}
+
+impl FieldError {
+    /// whether the field is, or is nested in, one of the sensitive ResourceProperties or OldResourceProperties
+    pub fn is_sensitive(&self, sensitive_properties: &[String]) -> bool {
+        let property = ["ResourceProperties.", "OldResourceProperties."].iter()
+            .find_map(|prefix| self.path.strip_prefix(prefix));
+        match property {
+            Some(property) => sensitive_properties.iter().any(|sensitive| {
+                matches!(property.strip_prefix(sensitive.as_str()), Some(rest) if rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
+            }),
+            None => false,
+        }
+    }
+}
+
+/// the errors with the message masked for sensitive fields, as serde quotes the offending value in it
+pub fn redact(errors: Vec<FieldError>, sensitive_properties: &[String]) -> Vec<FieldError> {
+    errors.into_iter()
+        .map(|error| match error.is_sensitive(sensitive_properties) {
+            true => FieldError { message: REDACTED.to_string(), ..error },
+            false => error,
+        })
+        .collect()
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Key {
+    Field(String),
+    Index(usize),
+    Other,
+}
+
+fn render(keys: &[Key]) -> String {
+    let mut path = String::new();
+    for key in keys {
+        match key {
+            Key::Field(name) if path.is_empty() => path.push_str(name),
+            Key::Field(name) => { path.push('.'); path.push_str(name) },
+            Key::Index(index) => path.push_str(&format!("[{}]", index)),
+            Key::Other => path.push('?'),
+        }
+    }
+    if path.is_empty() { ".".to_string() } else { path }
+}
+
+// the name between the backticks of serde's "missing field `x`" and "unknown field `x`, ..." messages
+fn named_field(message: &str, prefix: &str) -> Option<String> {
+    message.strip_prefix(prefix)
+        .and_then(|rest| rest.strip_prefix('`'))
+        .and_then(|rest| rest.split('`').next())
+        .map(|name| name.to_string())
+}
+
+struct Failure {
+    keys: Vec<Key>,
+    message: String,
+}
+
+fn attempt<T>(value: &Value) -> Result<T, Failure> where T: DeserializeOwned {
+    serde_path_to_error::deserialize::<_, T>(value).map_err(|err| {
+        let mut keys: Vec<Key> = err.path().iter().map(|segment| match segment {
+            Segment::Map { key } => Key::Field(key.clone()),
+            Segment::Seq { index } => Key::Index(*index),
+            _ => Key::Other,
+        }).collect();
+        let message = err.into_inner().to_string();
+
+        // missing and unknown fields may be reported against the enclosing object, point at the field itself
+        let named = named_field(&message, "missing field ")
+            .or_else(|| named_field(&message, "unknown field "))
+            .map(Key::Field);
+        if let Some(key) = named.filter(|key| keys.last() != Some(key)) {
+            keys.push(key);
+        }
+        Failure { keys, message }
+    })
+}
+
+// the object holding the last key of the path, when every key leading to it can be followed
+fn parent_mut<'v>(value: &'v mut Value, keys: &[Key]) -> Option<(&'v mut serde_json::Map<String, Value>, String)> {
+    let (last, leading) = keys.split_last()?;
+    let mut current = value;
+    for key in leading {
+        current = match key {
+            Key::Field(name) => current.get_mut(name.as_str())?,
+            Key::Index(index) => current.get_mut(*index)?,
+            Key::Other => return None,
+        };
+    }
+    match last {
+        Key::Field(name) => current.as_object_mut().map(|object| (object, name.clone())),
+        _ => None,
+    }
+}
+
+/// Deserialize the value, reporting every offending field rather than only the first.
+///
+/// serde stops at the first failure, so each failing field is recorded, replaced with a placeholder in a copy
+/// of the value, and the value is deserialized again. This ends when nothing else fails, or when a failure
+/// cannot be patched around, e.g. an array element of the wrong type.
+pub fn deserialize<T>(value: &Value) -> Result<T, Vec<FieldError>> where T: DeserializeOwned {
+    let mut failure = match attempt::<T>(value) {
+        Ok(deserialized) => return Ok(deserialized),
+        Err(failure) => failure,
+    };
+
+    let mut value = value.clone();
+    let mut errors: Vec<FieldError> = Vec::new();
+    loop {
+        let path = render(&failure.keys);
+        if errors.iter().any(|error| error.path == path) {
+            break;
+        }
+        errors.push(FieldError { path, message: failure.message });
+        if errors.len() >= MAX_FIELD_ERRORS {
+            break;
+        }
+
+        // keep the first placeholder that moves the failure away from the offending field
+        let keys = failure.keys;
+        let next = PLACEHOLDERS.iter().find_map(|placeholder| {
+            let (object, name) = parent_mut(&mut value, &keys)?;
+            match placeholder {
+                Some(placeholder) => object.insert(name, placeholder()),
+                None => object.remove(&name),
+            };
+            match attempt::<T>(&value) {
+                Ok(_) => Some(None),
+                Err(next) if !next.keys.starts_with(&keys) => Some(Some(next)),
+                Err(_) => None,
+            }
+        });
+
+        match next {
+            Some(Some(next)) => failure = next,
+            _ => break,
+        }
+    }
+    Err(errors)
+}
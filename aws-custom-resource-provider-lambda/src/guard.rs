@@ -0,0 +1,34 @@
+use aws_custom_resource_provider_events::{
+    ProviderResponse,
+    ProviderResponseBuilder,
+    ResponseStatus,
+};
+use futures::FutureExt;
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use tracing::error;
+
+/// Run a provider future, converting a panic into a FAILED response from the given builder.
+pub async fn catch_panic<F>(fallback: ProviderResponseBuilder, provider_future: F) -> ProviderResponse
+where F: Future<Output = ProviderResponse>
+{
+    match AssertUnwindSafe(provider_future).catch_unwind().await {
+        Ok(provider_response) => provider_response,
+        Err(panic) => {
+            let message = panic_message(panic.as_ref());
+            error!("Provider panicked: {}", &message);
+            fallback
+                .status(ResponseStatus::Failed)
+                .reason(format!("Provider panicked: {}", message))
+                .build()
+        }
+    }
+}
+
+// panic!() payloads are either a &'static str or a formatted String
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or("<non-string panic payload>".to_string())
+}
@@ -0,0 +1,122 @@
+use crate::error::{ Error, Error::* };
+use async_trait::async_trait;
+use aws_custom_resource_provider_events::ProviderResponse;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Uniquely identifies a request on a particular custom resource.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey {
+    pub stack_id: String,
+    pub request_id: String,
+}
+
+impl IdempotencyKey {
+    pub fn new(stack_id: String, request_id: String) -> Self {
+        IdempotencyKey {
+            stack_id,
+            request_id,
+        }
+    }
+
+    /// the key of a raw request event, None when either the StackId or RequestId is missing
+    pub fn from_value(event: &Value) -> Option<Self> {
+        let field = |name: &str| event.get(name)
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
+        Some(IdempotencyKey::new(field("StackId")?, field("RequestId")?))
+    }
+
+    /// a hex SHA-256 digest of the key, safe to use as a file name or partition key
+    pub fn digest(&self) -> String {
+        let mut key_dig_ctx = ring::digest::Context::new(&ring::digest::SHA256);
+        key_dig_ctx.update(self.stack_id.as_bytes());
+        key_dig_ctx.update(b"\n");
+        key_dig_ctx.update(self.request_id.as_bytes());
+        key_dig_ctx.finish().as_ref().iter().map(|b| format!("{:02x}",b)).collect::<String>()
+    }
+}
+
+/// Records the response sent for each request, so a retried invocation replays it
+/// instead of running the provider again. Implement this for DynamoDB or similar shared stores.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync + fmt::Debug {
+    async fn get(&self, key: &IdempotencyKey) -> Result<Option<ProviderResponse>, Error>;
+    async fn put(&self, key: &IdempotencyKey, response: &ProviderResponse) -> Result<(), Error>;
+}
+
+/// Keeps responses in the memory of the Lambda execution environment, which covers retries
+/// that land on the same warm instance.
+#[derive(Debug, Default)]
+pub struct MemoryIdempotencyStore {
+    responses: Mutex<HashMap<IdempotencyKey, ProviderResponse>>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        MemoryIdempotencyStore::default()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn get(&self, key: &IdempotencyKey) -> Result<Option<ProviderResponse>, Error> {
+        let responses = self.responses.lock()
+            .map_err(|e| IdempotencyStoreFailure(e.to_string()))?;
+        Ok(responses.get(key).cloned())
+    }
+    async fn put(&self, key: &IdempotencyKey, response: &ProviderResponse) -> Result<(), Error> {
+        let mut responses = self.responses.lock()
+            .map_err(|e| IdempotencyStoreFailure(e.to_string()))?;
+        responses.insert(key.clone(), response.clone());
+        Ok(())
+    }
+}
+
+/// Keeps each response as a JSON file in a local directory, such as /tmp or a mounted EFS volume.
+#[derive(Clone, Debug)]
+pub struct FileIdempotencyStore {
+    directory: PathBuf,
+}
+
+impl FileIdempotencyStore {
+    pub fn new<D>(directory: D) -> Self where D: Into<PathBuf> {
+        FileIdempotencyStore {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, key: &IdempotencyKey) -> PathBuf {
+        self.directory.join(format!("{}.json", key.digest()))
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for FileIdempotencyStore {
+    async fn get(&self, key: &IdempotencyKey) -> Result<Option<ProviderResponse>, Error> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| IdempotencyStoreFailure(e.to_string())),
+            Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(io_err) => Err(IdempotencyStoreFailure(io_err.to_string())),
+        }
+    }
+    async fn put(&self, key: &IdempotencyKey, response: &ProviderResponse) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(response)
+            .map_err(InvalidResponse)?;
+        tokio::fs::create_dir_all(&self.directory).await
+            .map_err(|e| IdempotencyStoreFailure(e.to_string()))?;
+        // write then rename, so a concurrent reader never sees a partial response
+        let path = self.path(key);
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, bytes).await
+            .map_err(|e| IdempotencyStoreFailure(e.to_string()))?;
+        tokio::fs::rename(&partial, &path).await
+            .map_err(|e| IdempotencyStoreFailure(e.to_string()))
+    }
+}
@@ -0,0 +1,111 @@
+use crate::service::{ ProviderRequest, ServiceFuture };
+use aws_custom_resource_provider_events::{
+    ProviderRequestEventDetails,
+    ProviderResponse,
+};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::task::{ Context, Poll };
+use std::time::Instant;
+use tower::{ Layer, Service };
+use tracing::{ info, error };
+
+//--- Logging
+/// Logs every request handed to the provider, and the status and reason of its response.
+#[derive(Clone, Debug, Default)]
+pub struct LoggingLayer;
+
+impl <S> Layer<S> for LoggingLayer {
+    type Service = LoggingService<S>;
+    fn layer(&self, inner: S) -> Self::Service {
+        LoggingService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggingService<S> {
+    inner: S,
+}
+
+impl <S, C, U, D, O> Service<ProviderRequest<C, U, D, O>> for LoggingService<S>
+where
+    S: Service<ProviderRequest<C, U, D, O>, Response = ProviderResponse>,
+    S::Error: fmt::Display + Send + 'static,
+    S::Future: Send + 'static,
+    C: DeserializeOwned,
+    U: DeserializeOwned,
+    D: DeserializeOwned,
+    O: DeserializeOwned,
+{
+    type Response = ProviderResponse;
+    type Error = S::Error;
+    type Future = ServiceFuture<S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ProviderRequest<C, U, D, O>) -> Self::Future {
+        let request_type = request.event.request_type();
+        let logical_resource_id = request.event.logical_resource_id();
+        info!("{:?} {} started", request_type, logical_resource_id);
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            match &response {
+                Ok(provider_response) =>
+                    info!("{:?} {} finished, status: {:?}, reason: {}", request_type, logical_resource_id, provider_response.status, provider_response.reason),
+                Err(err) =>
+                    error!("{:?} {} failed: {}", request_type, logical_resource_id, err),
+            }
+            response
+        })
+    }
+}
+
+//--- Timing
+/// Logs how long the provider took to respond to each request.
+#[derive(Clone, Debug, Default)]
+pub struct TimingLayer;
+
+impl <S> Layer<S> for TimingLayer {
+    type Service = TimingService<S>;
+    fn layer(&self, inner: S) -> Self::Service {
+        TimingService { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TimingService<S> {
+    inner: S,
+}
+
+impl <S, C, U, D, O> Service<ProviderRequest<C, U, D, O>> for TimingService<S>
+where
+    S: Service<ProviderRequest<C, U, D, O>, Response = ProviderResponse>,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+    C: DeserializeOwned,
+    U: DeserializeOwned,
+    D: DeserializeOwned,
+    O: DeserializeOwned,
+{
+    type Response = ProviderResponse;
+    type Error = S::Error;
+    type Future = ServiceFuture<S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ProviderRequest<C, U, D, O>) -> Self::Future {
+        let request_type = request.event.request_type();
+        let started = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            info!("{:?} took {} ms", request_type, started.elapsed().as_millis());
+            response
+        })
+    }
+}
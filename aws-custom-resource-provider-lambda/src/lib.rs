@@ -1,45 +1,96 @@
 pub mod types;
 pub mod error;
+pub mod sender;
+pub mod guard;
+pub mod context;
+pub mod router;
+pub mod idempotency;
+pub mod service;
+pub mod layers;
+pub mod redact;
+pub mod fields;
+pub mod response_url;
+pub mod allowlist;
+pub mod overflow;
+pub mod replacement;
 
 use error::*;
 use types::*;
+use sender::*;
+use context::*;
+use idempotency::*;
+use service::*;
+use redact::*;
+use response_url::*;
+use allowlist::*;
+use overflow::*;
+use replacement::*;
 
 use std::str::FromStr;
-use tracing::info;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn, error, info_span, Instrument};
 use lambda_runtime::{LambdaEvent, Error as LambdaError};
 use serde_json::{Value};
+use tower::{Layer, Service, ServiceExt};
+use tower::layer::util::{Identity, Stack};
 
 pub type Request = LambdaEvent<Value>;
 pub type Response = Result<(), LambdaError>;
 pub type Client = hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
 
+// time reserved ahead of the deadline for writing the FAILED response when a provider overruns.
+pub const DEFAULT_DEADLINE_MARGIN: Duration = Duration::from_secs(3);
+
 use aws_custom_resource_provider_events::{
+    ProviderResponse,
+    ProviderResponseBuilder,
+    ResponseStatus,
+    OversizeStrategy,
     RequestType,
     request_type,
+    service_timeout,
+    physical_id::validate as validate_physical_id,
+    PhysicalIdStrategy,
 };
 use serde::de::DeserializeOwned;
 
 #[derive(Clone, Debug)]
-pub struct HandlerConfig<P> 
+pub struct HandlerConfig<P, L = Identity>
 where P: Provider
 {
     pub provider: P,
     pub hyper: Client,
+    pub retry: RetryPolicy,
+    pub deadline_margin: Duration,
+    pub idempotency: Option<Arc<dyn IdempotencyStore>>,
+    pub allowlist: ResponseUrlAllowlist,
+    pub overflow: Option<DataOverflow>,
+    pub oversize: Vec<OversizeStrategy>,
+    pub layer: L,
 }
 
-impl <P, C, U, D> HandlerConfig<P>
-where 
+impl <P, C, U, D, O> HandlerConfig<P>
+where
      P: Provider<Create=CreateEvent<C>,
-                 Update=UpdateEvent<U>,
+                 Update=UpdateEvent<U, O>,
                  Delete=DeleteEvent<D>>,
     C: DeserializeOwned,
     U: DeserializeOwned,
     D: DeserializeOwned,
+    O: DeserializeOwned,
 {
     pub fn new_with_client(provider:P, client: Client) -> HandlerConfig<P> {
         HandlerConfig {
             provider: provider,
             hyper: client,
+            retry: RetryPolicy::default(),
+            deadline_margin: DEFAULT_DEADLINE_MARGIN,
+            idempotency: None,
+            allowlist: ResponseUrlAllowlist::default(),
+            overflow: None,
+            oversize: vec![OversizeStrategy::TruncateReason, OversizeStrategy::Fail],
+            layer: Identity::new(),
         }
     }
     pub fn new(provider: P) -> HandlerConfig<P> {
@@ -55,57 +106,348 @@ where
     }
 }
 
+impl <P, L> HandlerConfig<P, L>
+where P: Provider
+{
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> HandlerConfig<P, L> {
+        self.retry = retry;
+        self
+    }
+    pub fn with_deadline_margin(mut self, deadline_margin: Duration) -> HandlerConfig<P, L> {
+        self.deadline_margin = deadline_margin;
+        self
+    }
+    pub fn with_idempotency_store<S>(mut self, store: S) -> HandlerConfig<P, L> where S: IdempotencyStore + 'static {
+        self.idempotency = Some(Arc::new(store));
+        self
+    }
+    // restrict where responses are written, the CloudFormation response buckets of the function's region by default
+    pub fn with_response_allowlist(mut self, allowlist: ResponseUrlAllowlist) -> HandlerConfig<P, L> {
+        self.allowlist = allowlist;
+        self
+    }
+    // move Data values that do not fit in the 4 KB response into the store, sending references in their place
+    pub fn with_data_overflow<S>(mut self, store: S) -> HandlerConfig<P, L> where S: BlobStore + 'static {
+        self.overflow = Some(DataOverflow::new(store));
+        self
+    }
+    // what to do with a response over the 4 KB CloudFormation accepts, in order, TruncateReason then Fail by default.
+    // the response to a Delete has its Data dropped instead of failing, see fit
+    pub fn with_oversize_strategies(mut self, oversize: Vec<OversizeStrategy>) -> HandlerConfig<P, L> {
+        self.oversize = oversize;
+        self
+    }
+    // wrap the provider service in another layer, the first layer added is the outermost.
+    pub fn with_layer<N>(self, layer: N) -> HandlerConfig<P, Stack<N, L>> {
+        HandlerConfig {
+            provider: self.provider,
+            hyper: self.hyper,
+            retry: self.retry,
+            deadline_margin: self.deadline_margin,
+            idempotency: self.idempotency,
+            allowlist: self.allowlist,
+            overflow: self.overflow,
+            oversize: self.oversize,
+            layer: Stack::new(layer, self.layer),
+        }
+    }
+}
+
 
 // 1. determine what the request type is
 // 2. deserialize request event for given request type.
-// 3. delegate to request-type-specific handler within the provider
+// 3. delegate to request-type-specific handler within the provider, through any configured layers
 // 4. receive response result from request-type handler
 // 5. write reponse to the presigned s3 url
-pub async fn custom_resource_handler<P, C, U, D>(config: HandlerConfig<P>, event: Request) -> Response
-where 
+//
+// once the ResponseURL is known, every failure is reported to CloudFormation as a FAILED
+// response and the invocation succeeds, so the async invoke is not retried by Lambda.
+pub async fn custom_resource_handler<P, L, C, U, D, O>(config: HandlerConfig<P, L>, event: Request) -> Response
+where
     P: Provider<Create=CreateEvent<C>,
-                Update=UpdateEvent<U>,
-                Delete=DeleteEvent<D>>,
-    C: DeserializeOwned,
-    U: DeserializeOwned,
-    D: DeserializeOwned,
+                Update=UpdateEvent<U, O>,
+                Delete=DeleteEvent<D>> + Send + Sync + 'static,
+    L: Layer<ProviderService<P>>,
+    L::Service: Service<ProviderRequest<C, U, D, O>, Response=ProviderResponse>,
+    <L::Service as Service<ProviderRequest<C, U, D, O>>>::Error: Into<BoxError>,
+    C: DeserializeOwned + Send + 'static,
+    U: DeserializeOwned + Send + 'static,
+    D: DeserializeOwned + Send + 'static,
+    O: DeserializeOwned + Send + 'static,
 {
-    use RequestType::*;
-    use Error::*;
-    info!("Lambda invoked, event: {:?}", &event);
-    
-    let request = event.payload;
+    // every log line of the invocation carries the identity of the request, none carry the ResponseURL signature
+    let field = |name: &str| event.payload.get(name).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    let span = info_span!("custom_resource",
+        request_type = %field("RequestType"),
+        stack_id = %field("StackId"),
+        request_id = %field("RequestId"),
+        logical_resource_id = %field("LogicalResourceId"),
+        lambda_request_id = %event.context.request_id,
+    );
+    handle(config, event).instrument(span).await
+}
 
-    let response_url = request.get("ResponseURL")
+async fn handle<P, L, C, U, D, O>(config: HandlerConfig<P, L>, event: Request) -> Response
+where
+    P: Provider<Create=CreateEvent<C>,
+                Update=UpdateEvent<U, O>,
+                Delete=DeleteEvent<D>> + Send + Sync + 'static,
+    L: Layer<ProviderService<P>>,
+    L::Service: Service<ProviderRequest<C, U, D, O>, Response=ProviderResponse>,
+    <L::Service as Service<ProviderRequest<C, U, D, O>>>::Error: Into<BoxError>,
+    C: DeserializeOwned + Send + 'static,
+    U: DeserializeOwned + Send + 'static,
+    D: DeserializeOwned + Send + 'static,
+    O: DeserializeOwned + Send + 'static,
+{
+    let sensitive_properties = config.provider.sensitive_properties();
+    let redacted = redact_event(&event.payload, &sensitive_properties);
+    info!(event = %redacted, "Lambda invoked");
+
+    let response_url = event.payload.get("ResponseURL")
         .and_then(|v| v.as_str())
         .ok_or( Error::InvalidResponseURI() )
-        .and_then(|s|
-            hyper::Uri::from_str(&s)
-            .map_err(|_| Error::InvalidResponseURI() )
-        )?;
-
-    let req_type = request_type(&request)
-        .ok_or(InvalidRequestType(request.clone()))?;
-
-    let provider_response = match req_type {
-        Create => {config.provider.create(CreateEvent::try_from(request.clone())?).await},
-        Update => {config.provider.update(UpdateEvent::try_from(request.clone())?).await},
-        Delete => {config.provider.delete(DeleteEvent::try_from(request.clone())?).await},
+        .and_then(ResponseUrl::from_str)?;
+
+    let context = ProviderContext::new(event.context, config.deadline_margin)
+        .with_service_timeout(service_timeout(&event.payload), response_url.signed_at());
+    let request = event.payload;
+
+    // the provider output is only ever sent to an allowed endpoint. the invocation still succeeds,
+    // a retry by Lambda would be refused the same way
+    if !config.allowlist.allows(&response_url, context.region()) {
+        error!("Not invoking the provider: {}", Error::DisallowedResponseURI(response_url.to_string()).reason());
+        return Ok(());
+    }
+
+    // no response can be written to an expired URL, so the provider is not run at all.
+    // the invocation still succeeds, a retry by Lambda would find the URL just as expired
+    if let (true, Some(expires_at)) = (response_url.is_expired(), response_url.expires_at()) {
+        error!("Not invoking the provider: {}", Error::ExpiredResponseURI(expires_at).reason());
+        return Ok(());
+    }
+    match response_url.time_remaining() {
+        Some(remaining) => info!("ResponseURL to {} valid for another {} s", response_url.host().unwrap_or_default(), remaining.as_secs()),
+        None => info!("ResponseURL to {} has no expiry", response_url.host().unwrap_or_default()),
+    }
+
+    // a retried invocation replays the response recorded for the request, rather than running the provider again
+    let idempotency = config.idempotency.as_ref()
+        .and_then(|store| IdempotencyKey::from_value(&request).map(|key| (store, key)));
+
+    let recorded = match idempotency {
+        Some((store, ref key)) => store.get(key).await
+            .unwrap_or_else(|err| {
+                warn!("Unable to read the idempotency store: {:?}", &err);
+                None
+            }),
+        None => None,
     };
 
-    let as_json_response = serde_json::to_string(&provider_response)
-        .map_err::<LambdaError, _>(std::convert::Into::into)?;
+    // the deadline the response has to be written by, taken before the context moves into the provider
+    let deadline = context.deadline();
+    let provider_response = match recorded {
+        Some(provider_response) => {
+            info!("Replaying the recorded {:?} response for request {}", provider_response.status, provider_response.request_id);
+            provider_response
+        },
+        None => {
+            let immutable_properties = config.provider.immutable_properties();
+            let strategy = config.provider.physical_id_strategy();
+            let service = config.layer.layer(ProviderService::new(config.provider));
+            let provider_response = invoke(service, &request, &immutable_properties, &sensitive_properties, strategy.as_ref(), context).await;
+            let provider_response = check_physical_id(provider_response, &request);
+            let provider_response = match config.overflow {
+                Some(ref overflow) => overflow.spill(provider_response).await,
+                None => provider_response,
+            };
+            let provider_response = fit(provider_response, &config.oversize, request_type(&request));
 
-    // the request includes a presigned s3 URL to write our response to
-    let s3_resp = hyper::Request::builder()
-        .method(hyper::Method::PUT)
-        .uri(response_url)
-        .header("content-type", "application/json")
-        .body(hyper::Body::from(as_json_response))
-        .map_err::<LambdaError, _>(std::convert::Into::into)?;
+            // the values moved out of the responses for a resource are removed once its Delete succeeds
+            if let (Some(ref overflow), Some(RequestType::Delete), ResponseStatus::Success) =
+                (&config.overflow, request_type(&request), &provider_response.status) {
+                if let Err(err) = overflow.delete(&request).await {
+                    warn!("Unable to remove the values of the resource from the blob store: {:?}", &err);
+                }
+            }
+            if let Some((store, ref key)) = idempotency {
+                if let Err(err) = store.put(key, &provider_response).await {
+                    warn!("Unable to record the response in the idempotency store: {:?}", &err);
+                }
+            }
+            provider_response
+        },
+    };
 
     // write the response to the presigned s3 URL
-    config.hyper.request(s3_resp).await?;
+    send_response(&config.hyper, &config.retry, response_url.uri(), &provider_response, deadline).await?;
     Ok(())
 }
 
+// run the provider service against the deadline, reporting any failure as a FAILED response
+async fn invoke<S, C, U, D, O>(service: S, request: &Value, immutable_properties: &[String], sensitive_properties: &[String], strategy: &dyn PhysicalIdStrategy, context: ProviderContext) -> ProviderResponse
+where
+    S: Service<ProviderRequest<C, U, D, O>, Response=ProviderResponse>,
+    S::Error: Into<BoxError>,
+    C: DeserializeOwned,
+    U: DeserializeOwned,
+    D: DeserializeOwned,
+    O: DeserializeOwned,
+{
+    let budget = context.time_budget();
+
+    // an Update of immutable properties is handed to the provider as the Create of a replacement
+    let handled = handle_replacement(request, immutable_properties, strategy, |event| async move {
+        let handled = async {
+            let event = ProviderEvent::try_from(event)
+                .map_err(|err| err.redact(sensitive_properties))?;
+            service.oneshot(ProviderRequest::new(event, context)).await
+                .map_err(Into::into)
+        };
+
+        // race the provider against the earlier of the Lambda deadline and ServiceTimeout, less the margin needed to report back
+        let handled: Result<ProviderResponse, BoxError> = match budget {
+            Some(budget) => tokio::time::timeout(budget, handled).await
+                .unwrap_or(Err(Error::ProviderTimeout(budget).into())),
+            None => handled.await,
+        };
+        handled
+    }).await;
+
+    // the FAILED response keeps the PhysicalResourceId of the request, as a failed replacement must
+    match handled {
+        Ok(provider_response) => provider_response,
+        Err(err) => {
+            error!("Unable to handle event: {:?}", &err);
+            let reason = match err.downcast_ref::<Error>() {
+                Some(err) => err.reason(),
+                None => error_chain(err.as_ref()),
+            };
+            ProviderResponseBuilder::from_value(request)
+                .status(ResponseStatus::Failed)
+                .reason(reason)
+                .build()
+        }
+    }
+}
+
+// CloudFormation rejects a response whose PhysicalResourceId is empty or over 1 KB, whichever way it was built.
+// the id is replaced with the one of the request, or the StackHash for a Create, and only a Delete still succeeds
+fn check_physical_id(provider_response: ProviderResponse, request: &Value) -> ProviderResponse {
+    let invalid = match validate_physical_id(&provider_response.physical_resource_id) {
+        Ok(()) => return provider_response,
+        Err(invalid) => invalid,
+    };
+    error!("Invalid response from the provider: {}", invalid);
+    let fallback = ProviderResponseBuilder::from_value(request).build().physical_resource_id;
+    match request_type(request) {
+        Some(RequestType::Delete) => ProviderResponse { physical_resource_id: fallback, ..provider_response },
+        _ => ProviderResponse {
+            status: ResponseStatus::Failed,
+            reason: format!("Invalid response from the provider: {}", invalid),
+            physical_resource_id: fallback,
+            ..provider_response
+        },
+    }
+}
+
+// CloudFormation rejects a response over 4 KB with an opaque error, report what is too large instead.
+// a Delete never fails for its size, that would leave the stack in DELETE_FAILED over Data nothing reads
+fn fit(provider_response: ProviderResponse, oversize: &[OversizeStrategy], request_type: Option<RequestType>) -> ProviderResponse {
+    let delete = [OversizeStrategy::TruncateReason, OversizeStrategy::DropData];
+    let oversize = match request_type {
+        Some(RequestType::Delete) => &delete,
+        _ => oversize,
+    };
+    match provider_response.clone().fit(oversize) {
+        Ok(fitted) if fitted != provider_response => {
+            warn!("Response of {} bytes was too large, sending {:?} with reason: {}",
+                provider_response.serialized_size(), fitted.status, fitted.reason);
+            fitted
+        },
+        Ok(fitted) => fitted,
+        Err(too_large) => {
+            error!("Unable to fit the response: {}", too_large);
+            provider_response
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replacement::tests::{ stack_hash, update };
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    // records which of its methods was called, and answers create with the given status
+    struct Recording {
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        create_status: ResponseStatus,
+    }
+
+    #[async_trait]
+    impl Provider for Recording {
+        type Create = CreateEvent<Value>;
+        type Update = UpdateEvent<Value>;
+        type Delete = DeleteEvent<Value>;
+
+        async fn create(&self, create_event: Self::Create, _context: &ProviderContext) -> ProviderResponse {
+            self.calls.lock().unwrap().push("create");
+            ProviderResponseBuilder::from_event(create_event.0).status(self.create_status.clone()).build()
+        }
+        async fn update(&self, update_event: Self::Update, _context: &ProviderContext) -> ProviderResponse {
+            self.calls.lock().unwrap().push("update");
+            ProviderResponseBuilder::from_event(update_event.0).status(ResponseStatus::Success).build()
+        }
+        async fn delete(&self, delete_event: Self::Delete, _context: &ProviderContext) -> ProviderResponse {
+            self.calls.lock().unwrap().push("delete");
+            ProviderResponseBuilder::from_event(delete_event.0).status(ResponseStatus::Success).build()
+        }
+        fn immutable_properties(&self) -> Vec<String> {
+            vec!["Name".to_string()]
+        }
+    }
+
+    async fn invoke_recording(request: &Value, create_status: ResponseStatus) -> (ProviderResponse, Vec<&'static str>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let provider = Recording { calls: calls.clone(), create_status };
+        let immutable_properties = provider.immutable_properties();
+        let strategy = provider.physical_id_strategy();
+        let context = ProviderContext::new(Default::default(), DEFAULT_DEADLINE_MARGIN);
+        let response = invoke(ProviderService::new(provider), request, &immutable_properties, &[], strategy.as_ref(), context).await;
+        let calls = calls.lock().unwrap().clone();
+        (response, calls)
+    }
+
+    #[tokio::test]
+    async fn update_of_an_immutable_property_creates_a_replacement() {
+        let (response, calls) = invoke_recording(&update("resource-existing", "new", "old"), ResponseStatus::Success).await;
+
+        assert_eq!(calls, vec!["create"]);
+        assert_eq!(response.status, ResponseStatus::Success);
+        assert_eq!(response.physical_resource_id, format!("{}-1", stack_hash()));
+    }
+
+    #[tokio::test]
+    async fn delete_of_the_replaced_resource_is_a_delete() {
+        // the Delete CloudFormation sends for the old resource once its replacement succeeded
+        let mut delete = update("resource-old", "new", "old");
+        delete["RequestType"] = Value::String("Delete".to_string());
+        delete.as_object_mut().unwrap().remove("OldResourceProperties");
+        let (response, calls) = invoke_recording(&delete, ResponseStatus::Success).await;
+
+        assert_eq!(calls, vec!["delete"]);
+        assert_eq!(response.status, ResponseStatus::Success);
+        assert_eq!(response.physical_resource_id, "resource-old");
+    }
+
+    #[tokio::test]
+    async fn update_of_mutable_properties_only_is_an_update() {
+        let (response, calls) = invoke_recording(&update("resource-existing", "same", "same"), ResponseStatus::Success).await;
+
+        assert_eq!(calls, vec!["update"]);
+        assert_eq!(response.status, ResponseStatus::Success);
+        assert_eq!(response.physical_resource_id, "resource-existing");
+    }
+}
@@ -0,0 +1,392 @@
+use crate::error::{ Error, Error::* };
+use async_trait::async_trait;
+use aws_custom_resource_provider_events::{
+    ProviderResponse,
+    ProviderResponseBuilder,
+    MAX_RESPONSE_SIZE,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Keeps Data values too large for the response. `put` returns the reference sent in their place,
+/// which `get` accepts to read the value back, e.g. from another function consuming the output.
+/// `delete` removes every value kept under a key prefix.
+#[async_trait]
+pub trait BlobStore: Send + Sync + fmt::Debug {
+    async fn put(&self, key: &str, value: &str) -> Result<String, Error>;
+    async fn get(&self, reference: &str) -> Result<String, Error>;
+    async fn delete(&self, prefix: &str) -> Result<(), Error>;
+}
+
+/*
+ * Moves the largest Data values of an oversized response into a BlobStore, until the response fits.
+ * Each value is kept under <hash of StackId, LogicalResourceId and PhysicalResourceId>/<Data key>, and Data
+ * carries the reference returned by the store instead of the value. An update of the resource overwrites its
+ * previous values, while a replacement keeps its own until the Delete of the resource it replaced, or its own
+ * Delete on rollback, removes the values of that resource only.
+ * Data may hold secrets, NoEcho or not: SsmBlobStore keeps every value as a SecureString, an S3BlobStore
+ * bucket should be encrypted and as restricted as the secrets it may receive.
+ */
+#[derive(Clone, Debug)]
+pub struct DataOverflow {
+    store: Arc<dyn BlobStore>,
+}
+
+impl DataOverflow {
+    pub fn new<S>(store: S) -> Self where S: BlobStore + 'static {
+        DataOverflow {
+            store: Arc::new(store),
+        }
+    }
+
+    /// the response with its largest Data values replaced by references, when it is over MAX_RESPONSE_SIZE.
+    /// a value that cannot be stored is left in place, for the size check of the handler to report.
+    pub async fn spill(&self, mut response: ProviderResponse) -> ProviderResponse {
+        let mut keys = match &response.data {
+            Some(Value::Object(data)) => data.iter()
+                .map(|(key, value)| (key.clone(), value.to_string()))
+                .collect::<Vec<_>>(),
+            _ => return response,
+        };
+        keys.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+
+        let resource = resource_prefix(&response);
+        for (key, value) in keys {
+            if response.serialized_size() <= MAX_RESPONSE_SIZE {
+                break;
+            }
+            match self.store.put(&format!("{}{}", resource, key), &value).await {
+                Ok(reference) => {
+                    info!("Data key {} of {} bytes moved to {}", key, value.len(), reference);
+                    if let Some(Value::Object(data)) = &mut response.data {
+                        data.insert(key, Value::String(reference));
+                    }
+                },
+                Err(err) => warn!("Unable to move Data key {} to the blob store: {:?}", key, &err),
+            }
+        }
+        response
+    }
+
+    /// remove the values moved out of the responses for a resource, given the request to delete it
+    pub async fn delete(&self, request: &Value) -> Result<(), Error> {
+        let resource = ProviderResponseBuilder::from_value(request).build();
+        self.store.delete(&resource_prefix(&resource)).await
+    }
+
+    /// read back a value moved out of a response, given the reference sent in its place
+    pub async fn resolve(&self, reference: &str) -> Result<Value, Error> {
+        resolve(self.store.as_ref(), reference).await
+    }
+}
+
+// the key prefix of the values of a resource, <hex SHA-256 digest>/
+fn resource_prefix(response: &ProviderResponse) -> String {
+    let mut resource_dig_ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    resource_dig_ctx.update(response.stack_id.as_bytes());
+    resource_dig_ctx.update(b"\n");
+    resource_dig_ctx.update(response.logical_resource_id.as_bytes());
+    resource_dig_ctx.update(b"\n");
+    resource_dig_ctx.update(response.physical_resource_id.as_bytes());
+    let digest = resource_dig_ctx.finish().as_ref().iter().map(|b| format!("{:02x}",b)).collect::<String>();
+    format!("{}/", digest)
+}
+
+/// read back a Data value moved to the store, given the reference sent in its place
+pub async fn resolve(store: &dyn BlobStore, reference: &str) -> Result<Value, Error> {
+    let value = store.get(reference).await?;
+    serde_json::from_str(&value)
+        .map_err(|e| BlobStoreFailure(format!("{} does not hold a JSON value: {}", reference, e)))
+}
+
+/// Keeps values in memory, for tests and local runs. References are `memory:<key>`.
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore {
+    values: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryBlobStore {
+    pub fn new() -> Self {
+        MemoryBlobStore::default()
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemoryBlobStore {
+    async fn put(&self, key: &str, value: &str) -> Result<String, Error> {
+        let mut values = self.values.lock()
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        values.insert(key.to_string(), value.to_string());
+        Ok(format!("memory:{}", key))
+    }
+    async fn get(&self, reference: &str) -> Result<String, Error> {
+        let key = reference.strip_prefix("memory:")
+            .ok_or_else(|| BlobStoreFailure(format!("{} is not a memory reference", reference)))?;
+        let values = self.values.lock()
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        values.get(key).cloned()
+            .ok_or_else(|| BlobStoreFailure(format!("{} not found", reference)))
+    }
+    async fn delete(&self, prefix: &str) -> Result<(), Error> {
+        let mut values = self.values.lock()
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        values.retain(|key, _| !key.starts_with(prefix));
+        Ok(())
+    }
+}
+
+/// Keeps values as objects in an S3 bucket, under an optional key prefix. References are `s3://<bucket>/<key>`.
+#[cfg(feature = "s3")]
+#[derive(Clone, Debug)]
+pub struct S3BlobStore {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+#[cfg(feature = "s3")]
+impl S3BlobStore {
+    pub fn new(client: aws_sdk_s3::Client, bucket: String) -> Self {
+        S3BlobStore {
+            client,
+            bucket,
+            prefix: String::new(),
+        }
+    }
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = prefix;
+        self
+    }
+}
+
+#[cfg(feature = "s3")]
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, value: &str) -> Result<String, Error> {
+        let key = format!("{}{}", self.prefix, key);
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/json")
+            .body(aws_sdk_s3::types::ByteStream::from(value.as_bytes().to_vec()))
+            .send()
+            .await
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        Ok(format!("s3://{}/{}", self.bucket, key))
+    }
+    async fn get(&self, reference: &str) -> Result<String, Error> {
+        let (bucket, key) = reference.strip_prefix("s3://")
+            .and_then(|location| location.split_once('/'))
+            .ok_or_else(|| BlobStoreFailure(format!("{} is not an s3 reference", reference)))?;
+        let object = self.client.get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        let bytes = object.body.collect().await
+            .map_err(|e| BlobStoreFailure(e.to_string()))?
+            .into_bytes();
+        String::from_utf8(bytes.to_vec())
+            .map_err(|e| BlobStoreFailure(e.to_string()))
+    }
+    async fn delete(&self, prefix: &str) -> Result<(), Error> {
+        let prefix = format!("{}{}", self.prefix, prefix);
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let listed = self.client.list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|e| BlobStoreFailure(e.to_string()))?;
+            keys.extend(listed.contents().unwrap_or_default().iter()
+                .filter_map(|object| object.key())
+                .map(|key| key.to_string()));
+            continuation_token = listed.next_continuation_token().map(|token| token.to_string());
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        for key in keys {
+            self.client.delete_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .send()
+                .await
+                .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps values as SecureString SSM parameters, named under a prefix such as `/custom-resources`, encrypted with
+/// the account's default SSM key unless another is given. References are `ssm:<name>`.
+/// Values over 4 KB need the advanced tier, which intelligent tiering selects as needed.
+#[cfg(feature = "ssm")]
+#[derive(Clone, Debug)]
+pub struct SsmBlobStore {
+    client: aws_sdk_ssm::Client,
+    prefix: String,
+    key_id: Option<String>,
+}
+
+#[cfg(feature = "ssm")]
+impl SsmBlobStore {
+    pub fn new(client: aws_sdk_ssm::Client, prefix: String) -> Self {
+        SsmBlobStore {
+            client,
+            prefix,
+            key_id: None,
+        }
+    }
+    // the KMS key the parameters are encrypted with, by id, ARN or alias
+    pub fn with_key_id(mut self, key_id: String) -> Self {
+        self.key_id = Some(key_id);
+        self
+    }
+
+    // the parameter name of a key, as parameter names only allow letters, digits and _.-/
+    fn name(&self, key: &str) -> String {
+        let key = key.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || "_.-/".contains(c) { c } else { '_' })
+            .collect::<String>();
+        format!("{}/{}", self.prefix.trim_end_matches('/'), key)
+    }
+}
+
+#[cfg(feature = "ssm")]
+#[async_trait]
+impl BlobStore for SsmBlobStore {
+    async fn put(&self, key: &str, value: &str) -> Result<String, Error> {
+        let name = self.name(key);
+        self.client.put_parameter()
+            .name(&name)
+            .value(value)
+            .r#type(aws_sdk_ssm::model::ParameterType::SecureString)
+            .set_key_id(self.key_id.clone())
+            .tier(aws_sdk_ssm::model::ParameterTier::IntelligentTiering)
+            .overwrite(true)
+            .send()
+            .await
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        Ok(format!("ssm:{}", name))
+    }
+    async fn get(&self, reference: &str) -> Result<String, Error> {
+        let name = reference.strip_prefix("ssm:")
+            .ok_or_else(|| BlobStoreFailure(format!("{} is not an ssm reference", reference)))?;
+        let output = self.client.get_parameter()
+            .name(name)
+            .with_decryption(true)
+            .send()
+            .await
+            .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        output.parameter()
+            .and_then(|parameter| parameter.value())
+            .map(|value| value.to_string())
+            .ok_or_else(|| BlobStoreFailure(format!("{} has no value", reference)))
+    }
+    async fn delete(&self, prefix: &str) -> Result<(), Error> {
+        let path = self.name(prefix);
+        let mut names = Vec::new();
+        let mut next_token = None;
+        loop {
+            let listed = self.client.get_parameters_by_path()
+                .path(path.trim_end_matches('/'))
+                .recursive(true)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .map_err(|e| BlobStoreFailure(e.to_string()))?;
+            names.extend(listed.parameters().unwrap_or_default().iter()
+                .filter_map(|parameter| parameter.name())
+                .map(|name| name.to_string()));
+            next_token = listed.next_token().map(|token| token.to_string());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        // DeleteParameters takes at most 10 names
+        for names in names.chunks(10) {
+            self.client.delete_parameters()
+                .set_names(Some(names.to_vec()))
+                .send()
+                .await
+                .map_err(|e| BlobStoreFailure(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_custom_resource_provider_events::{ ProviderResponseBuilder, ResponseStatus };
+    use serde_json::json;
+
+    fn request(physical_resource_id: &str) -> Value {
+        json!({ "StackId": "stack", "LogicalResourceId": "Resource", "RequestId": "request", "PhysicalResourceId": physical_resource_id })
+    }
+
+    fn response(physical_resource_id: &str, data: Value) -> ProviderResponse {
+        ProviderResponseBuilder::from_value(&request(physical_resource_id))
+            .status(ResponseStatus::Success)
+            .data(data)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn memory_store_reads_back_what_it_keeps() {
+        let store = MemoryBlobStore::new();
+        let reference = store.put("resource/key", "\"value\"").await.unwrap();
+        assert_eq!(reference, "memory:resource/key");
+        assert_eq!(resolve(&store, &reference).await.unwrap(), json!("value"));
+        assert!(store.get("memory:missing").await.is_err());
+        assert!(store.get("s3://bucket/resource/key").await.is_err());
+
+        store.put("other/key", "\"value\"").await.unwrap();
+        store.delete("resource/").await.unwrap();
+        assert!(store.get("memory:resource/key").await.is_err());
+        assert!(store.get("memory:other/key").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn spill_moves_the_largest_values_until_the_response_fits() {
+        let overflow = DataOverflow::new(MemoryBlobStore::new());
+        let large = "x".repeat(MAX_RESPONSE_SIZE);
+        let spilled = overflow.spill(response("resource", json!({ "Large": large, "Small": "kept" }))).await;
+
+        assert!(spilled.serialized_size() <= MAX_RESPONSE_SIZE);
+        let data = spilled.data.unwrap();
+        assert_eq!(data["Small"], json!("kept"));
+        let reference = data["Large"].as_str().unwrap();
+        assert!(reference.starts_with("memory:"));
+        assert_eq!(overflow.resolve(reference).await.unwrap(), json!(large));
+    }
+
+    #[tokio::test]
+    async fn spill_leaves_a_response_that_fits() {
+        let overflow = DataOverflow::new(MemoryBlobStore::new());
+        let fitting = response("resource", json!({ "Small": "kept" }));
+        assert_eq!(overflow.spill(fitting.clone()).await, fitting);
+    }
+
+    #[tokio::test]
+    async fn a_replacement_keeps_its_own_values_until_the_delete_of_the_resource() {
+        let overflow = DataOverflow::new(MemoryBlobStore::new());
+        let data = json!({ "Large": "x".repeat(MAX_RESPONSE_SIZE) });
+        let reference = |response: ProviderResponse| response.data.unwrap()["Large"].as_str().unwrap().to_string();
+        let existing = reference(overflow.spill(response("resource-1", data.clone())).await);
+        let replacement = reference(overflow.spill(response("resource-2", data)).await);
+        assert_ne!(existing, replacement);
+
+        overflow.delete(&request("resource-1")).await.unwrap();
+        assert!(overflow.resolve(&existing).await.is_err());
+        assert!(overflow.resolve(&replacement).await.is_ok());
+    }
+}
@@ -0,0 +1,53 @@
+use serde_json::Value;
+
+pub const REDACTED: &str = "*****";
+
+// query parameters of a presigned S3 URL that grant access to the response object, SigV4 then SigV2
+const SIGNING_PARAMETERS: [&str; 4] = ["X-Amz-Signature", "X-Amz-Credential", "X-Amz-Security-Token", "Signature"];
+
+/// Mask the signing query parameters of a presigned URL, leaving the rest readable.
+pub fn redact_url(url: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some(split) => split,
+        None => return url.to_string(),
+    };
+    let query = query.split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SIGNING_PARAMETERS.iter().any(|p| p.eq_ignore_ascii_case(name)) =>
+                format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", base, query)
+}
+
+/// A copy of a raw request event that is safe to log: the ResponseURL signature is masked,
+/// as are the given sensitive fields of the ResourceProperties and OldResourceProperties.
+/// Nested fields are named with a dotted path, e.g. `Credentials.Password`.
+pub fn redact_event(event: &Value, sensitive_properties: &[String]) -> Value {
+    let mut event = event.clone();
+    if let Some(Value::String(url)) = event.get_mut("ResponseURL") {
+        *url = redact_url(url);
+    }
+    for properties in ["ResourceProperties", "OldResourceProperties"] {
+        if let Some(properties) = event.get_mut(properties) {
+            for path in sensitive_properties {
+                redact_path(properties, path);
+            }
+        }
+    }
+    event
+}
+
+fn redact_path(value: &mut Value, path: &str) {
+    let (name, rest) = match path.split_once('.') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    };
+    match (value.get_mut(name), rest) {
+        (Some(field), None) => *field = Value::String(REDACTED.to_string()),
+        (Some(field), Some(rest)) => redact_path(field, rest),
+        (None, _) => {},
+    }
+}
@@ -0,0 +1,219 @@
+use aws_custom_resource_provider_events::{
+    build_physical_resource_id,
+    request_type,
+    PhysicalIdStrategy,
+    ProviderRequestCreateEvent,
+    ProviderRequestUpdateEvent,
+    ProviderResponse,
+    RequestType,
+    ResponseStatus,
+};
+use serde_json::Value;
+use std::future::Future;
+use tracing::{info, warn};
+
+// the value at a dotted path of the properties, e.g. `Credentials.Username`
+fn lookup<'v>(properties: Option<&'v Value>, path: &str) -> Option<&'v Value> {
+    path.split('.').fold(properties, |value, name| value.and_then(|v| v.get(name)))
+}
+
+/// the given dotted paths whose value differs between the ResourceProperties and OldResourceProperties of a raw event
+pub fn changed_properties(event: &Value, paths: &[String]) -> Vec<String> {
+    let properties = event.get("ResourceProperties");
+    let old_properties = event.get("OldResourceProperties");
+    paths.iter()
+        .filter(|path| lookup(properties, path) != lookup(old_properties, path))
+        .cloned()
+        .collect()
+}
+
+/// A raw Update event that changes any of the immutable properties, rewritten as the Create of the replacement
+/// resource, None for any other event. The provider then creates the resource anew, and the response to the
+/// Update carries a new PhysicalResourceId, after which CloudFormation deletes the old resource.
+pub fn replacement(event: &Value, immutable_properties: &[String]) -> Option<Value> {
+    if request_type(event) != Some(RequestType::Update) {
+        return None;
+    }
+    let changed = changed_properties(event, immutable_properties);
+    if changed.is_empty() {
+        return None;
+    }
+    info!("Update changes the immutable properties {}, creating a replacement", changed.join(", "));
+
+    let mut create = event.clone();
+    let fields = create.as_object_mut()?;
+    fields.insert("RequestType".to_string(), Value::String("Create".to_string()));
+    fields.remove("OldResourceProperties");
+    fields.remove("PhysicalResourceId");
+    Some(create)
+}
+
+/// The response to the Create of a replacement, as the response to the Update it was rewritten from.
+/// A failed replacement keeps the PhysicalResourceId of the existing resource. A successful one must not, so a
+/// Create that answered with the existing id, or with the StackHash every Create defaults to, is given the id the
+/// strategy generates for the Update, e.g. the next generation of the existing id.
+pub fn replacement_response(update: &Value, mut response: ProviderResponse, strategy: &dyn PhysicalIdStrategy) -> ProviderResponse {
+    let field = |name: &str| update.get(name)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let existing = field("PhysicalResourceId");
+    let stack_hash = build_physical_resource_id(field("StackId"), field("LogicalResourceId"));
+
+    match response.status {
+        ResponseStatus::Failed => response.physical_resource_id = existing,
+        ResponseStatus::Success if response.physical_resource_id == existing || response.physical_resource_id == stack_hash => {
+            let replaced = serde_json::from_value::<ProviderRequestUpdateEvent<Value>>(update.clone())
+                .map_err(|e| e.to_string())
+                .and_then(|event| strategy.physical_id(&event).map_err(|e| e.to_string()));
+            match replaced {
+                Ok(replaced) if replaced == existing =>
+                    warn!("The replacement is given the PhysicalResourceId of the existing resource, which CloudFormation will not delete"),
+                Ok(replaced) => response.physical_resource_id = replaced,
+                Err(err) => warn!("Unable to generate a PhysicalResourceId for the replacement: {}", err),
+            }
+        },
+        ResponseStatus::Success => {},
+    }
+    response
+}
+
+/// The response to a Create, given the id the strategy generates when it succeeded with the StackHash every Create
+/// defaults to. An id the provider chose itself is kept, as is the StackHash when the strategy cannot generate one.
+pub fn created_response(create: &Value, mut response: ProviderResponse, strategy: &dyn PhysicalIdStrategy) -> ProviderResponse {
+    let stack_hash = build_physical_resource_id(response.stack_id.clone(), response.logical_resource_id.clone());
+    if response.status != ResponseStatus::Success || response.physical_resource_id != stack_hash {
+        return response;
+    }
+    let created = serde_json::from_value::<ProviderRequestCreateEvent<Value>>(create.clone())
+        .map_err(|e| e.to_string())
+        .and_then(|event| strategy.physical_id(&event).map_err(|e| e.to_string()));
+    match created {
+        Ok(created) => response.physical_resource_id = created,
+        Err(err) => warn!("Unable to generate a PhysicalResourceId for the resource, keeping {}: {}", stack_hash, err),
+    }
+    response
+}
+
+/// Hand the raw request to `handle`, rewritten as the Create of a replacement when it is an Update changing
+/// any of the immutable properties, in which case the response is the one to the Update, see replacement_response.
+/// The response to a Create is named by the strategy, see created_response.
+pub async fn handle_replacement<F, Fut, E>(request: &Value, immutable_properties: &[String], strategy: &dyn PhysicalIdStrategy, handle: F) -> Result<ProviderResponse, E>
+where F: FnOnce(Value) -> Fut,
+      Fut: Future<Output = Result<ProviderResponse, E>>,
+{
+    match replacement(request, immutable_properties) {
+        Some(create) => handle(create).await
+            .map(|response| replacement_response(request, response, strategy)),
+        None if request_type(request) == Some(RequestType::Create) => handle(request.clone()).await
+            .map(|response| created_response(request, response, strategy)),
+        None => handle(request.clone()).await,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use aws_custom_resource_provider_events::{ physical_id::{ GenerationalHash, NaturalId, Template }, ProviderResponseBuilder };
+    use serde_json::json;
+
+    pub(crate) const STACK_ID: &str = "arn:aws:cloudformation:us-east-1:123456789012:stack/stack/uuid";
+
+    pub(crate) fn stack_hash() -> String {
+        build_physical_resource_id(STACK_ID.to_string(), "Resource".to_string())
+    }
+
+    // an Update of the resource, changing the immutable Name when name and old_name differ
+    pub(crate) fn update(physical_resource_id: &str, name: &str, old_name: &str) -> Value {
+        json!({
+            "RequestType": "Update",
+            "ResponseURL": "https://example.com/response",
+            "StackId": STACK_ID,
+            "RequestId": "request",
+            "ResourceType": "Custom::Resource",
+            "LogicalResourceId": "Resource",
+            "PhysicalResourceId": physical_resource_id,
+            "ResourceProperties": { "Name": name, "Size": 2 },
+            "OldResourceProperties": { "Name": old_name, "Size": 1 },
+        })
+    }
+
+    // the response a provider builds for the Create of the replacement, without choosing an id
+    fn created(create: &Value, status: ResponseStatus) -> ProviderResponse {
+        ProviderResponseBuilder::from_value(create).status(status).build()
+    }
+
+    #[test]
+    fn update_of_an_immutable_property_becomes_a_create() {
+        let update = update(&stack_hash(), "new", "old");
+        let create = replacement(&update, &["Name".to_string()]).unwrap();
+
+        assert_eq!(create["RequestType"], json!("Create"));
+        assert_eq!(create["ResourceProperties"], update["ResourceProperties"]);
+        assert!(create.get("PhysicalResourceId").is_none());
+        assert!(create.get("OldResourceProperties").is_none());
+    }
+
+    #[test]
+    fn update_of_mutable_properties_only_is_left_alone() {
+        let update = update(&stack_hash(), "same", "same");
+        assert_eq!(replacement(&update, &["Name".to_string()]), None);
+        assert_eq!(replacement(&update, &[]), None);
+        assert_eq!(changed_properties(&update, &["Name".to_string(), "Size".to_string()]), vec!["Size".to_string()]);
+    }
+
+    #[test]
+    fn successful_replacement_gets_a_new_id_each_time() {
+        let first = update(&stack_hash(), "new", "old");
+        let response = replacement_response(&first, created(&replacement(&first, &["Name".to_string()]).unwrap(), ResponseStatus::Success), &GenerationalHash);
+        assert_eq!(response.physical_resource_id, format!("{}-1", stack_hash()));
+
+        // the generation keeps counting up, rather than going back to the id every Create defaults to
+        let second = update(&response.physical_resource_id, "newer", "new");
+        let response = replacement_response(&second, created(&replacement(&second, &["Name".to_string()]).unwrap(), ResponseStatus::Success), &GenerationalHash);
+        assert_eq!(response.physical_resource_id, format!("{}-2", stack_hash()));
+    }
+
+    #[test]
+    fn successful_replacement_uses_the_strategy_of_the_provider() {
+        let update = update(&stack_hash(), "new", "old");
+        let create = replacement(&update, &["Name".to_string()]).unwrap();
+        let response = replacement_response(&update, created(&create, ResponseStatus::Success), &NaturalId("resource-new".to_string()));
+        assert_eq!(response.physical_resource_id, "resource-new");
+
+        // an id the provider chose itself is kept
+        let chosen = ProviderResponseBuilder::from_value(&create)
+            .status(ResponseStatus::Success)
+            .physical_resource_id("resource-chosen".to_string())
+            .build();
+        assert_eq!(replacement_response(&update, chosen, &GenerationalHash).physical_resource_id, "resource-chosen");
+    }
+
+    #[test]
+    fn failed_replacement_keeps_the_existing_id() {
+        let update = update("resource-existing", "new", "old");
+        let create = replacement(&update, &["Name".to_string()]).unwrap();
+        let response = replacement_response(&update, created(&create, ResponseStatus::Failed), &GenerationalHash);
+        assert_eq!(response.status, ResponseStatus::Failed);
+        assert_eq!(response.physical_resource_id, "resource-existing");
+    }
+
+    #[test]
+    fn successful_create_is_named_by_the_strategy() {
+        let create = replacement(&update(&stack_hash(), "new", "old"), &["Name".to_string()]).unwrap();
+        let named = |status, strategy: &dyn PhysicalIdStrategy| created_response(&create, created(&create, status), strategy).physical_resource_id;
+
+        // the default strategy starts from the id every Create defaults to
+        assert_eq!(named(ResponseStatus::Success, &GenerationalHash), stack_hash());
+        assert_eq!(named(ResponseStatus::Success, &Template("{StackName}-{LogicalResourceId}".to_string())), "stack-Resource");
+        assert_eq!(named(ResponseStatus::Failed, &Template("{StackName}-{LogicalResourceId}".to_string())), stack_hash());
+        assert_eq!(named(ResponseStatus::Success, &Template("{Unknown}".to_string())), stack_hash());
+
+        // an id the provider chose itself is kept
+        let chosen = ProviderResponseBuilder::from_value(&create)
+            .status(ResponseStatus::Success)
+            .physical_resource_id("resource-chosen".to_string())
+            .build();
+        assert_eq!(created_response(&create, chosen, &NaturalId("resource-new".to_string())).physical_resource_id, "resource-chosen");
+    }
+}
@@ -0,0 +1,173 @@
+use crate::error::Error;
+use crate::redact::redact_url;
+use hyper::Uri;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/*
+ * The presigned S3 URL a response is written to, with the validity window read from its query:
+ * X-Amz-Date and X-Amz-Expires for SigV4 URLs, or the Expires timestamp of SigV2 URLs.
+ * Display and Debug mask the signature, so a ResponseUrl is safe to log.
+ */
+#[derive(Clone)]
+pub struct ResponseUrl {
+    uri: Uri,
+    signed_at: Option<SystemTime>,
+    expires_at: Option<SystemTime>,
+}
+
+impl ResponseUrl {
+    pub fn uri(&self) -> &Uri { &self.uri }
+
+    /// the bucket host the response is written to
+    pub fn host(&self) -> Option<&str> { self.uri.host() }
+
+    /// when the URL was signed, from X-Amz-Date
+    pub fn signed_at(&self) -> Option<SystemTime> { self.signed_at }
+
+    /// when the URL stops accepting the response, None when the URL carries no expiry
+    pub fn expires_at(&self) -> Option<SystemTime> { self.expires_at }
+
+    /// how long the URL remains valid, zero once it has expired
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| expires_at.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.time_remaining() == Some(Duration::ZERO)
+    }
+}
+
+impl FromStr for ResponseUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uri = Uri::from_str(s).map_err(|_| Error::InvalidResponseURI())?;
+        if uri.host().is_none() {
+            return Err(Error::InvalidResponseURI());
+        }
+
+        let parameter = |name: &str| uri.query()
+            .and_then(|query| query.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_string()));
+
+        let signed_at = parameter("X-Amz-Date").and_then(|date| parse_amz_date(&date));
+        let expires_at = match (signed_at, parameter("X-Amz-Expires")) {
+            (Some(signed_at), Some(expires)) => expires.parse::<u64>().ok()
+                .map(|expires| signed_at + Duration::from_secs(expires)),
+            _ => parameter("Expires")
+                .and_then(|expires| expires.parse::<u64>().ok())
+                .map(|expires| UNIX_EPOCH + Duration::from_secs(expires)),
+        };
+
+        Ok(ResponseUrl { uri, signed_at, expires_at })
+    }
+}
+
+impl fmt::Display for ResponseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", redact_url(&self.uri.to_string()))
+    }
+}
+
+impl fmt::Debug for ResponseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseUrl")
+            .field("uri", &format_args!("{}", self))
+            .field("signed_at", &self.signed_at)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+// an X-Amz-Date timestamp, e.g. 20221115T203000Z, which is always UTC
+fn parse_amz_date(date: &str) -> Option<SystemTime> {
+    if date.len() != 16 || date.get(8..9) != Some("T") || !date.ends_with('Z') {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| date.get(range).and_then(|digits| digits.parse::<u64>().ok());
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hour, minute, second) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // days since the epoch of a proleptic Gregorian date, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year.checked_sub(1)? } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime { UNIX_EPOCH + Duration::from_secs(seconds) }
+
+    #[test]
+    fn amz_dates_are_read_as_utc() {
+        assert_eq!(parse_amz_date("19700101T000000Z"), Some(at(0)));
+        assert_eq!(parse_amz_date("20221115T203000Z"), Some(at(1668544200)));
+        assert_eq!(parse_amz_date("20240229T000000Z"), Some(at(1709164800)));
+    }
+
+    #[test]
+    fn impossible_amz_dates_are_rejected() {
+        for date in ["20230230T000000Z", "20230229T000000Z", "21000229T000000Z", "20230431T000000Z",
+                     "20231301T000000Z", "20230100T000000Z", "20230101T240000Z", "20230101T000000", "2023-01-01T00:00:00Z"] {
+            assert_eq!(parse_amz_date(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn sigv4_urls_expire_after_x_amz_expires() {
+        let url: ResponseUrl = "https://bucket.s3.amazonaws.com/key?X-Amz-Algorithm=AWS4-HMAC-SHA256\
+            &X-Amz-Date=20221115T203000Z&X-Amz-Expires=7200&X-Amz-Signature=abc".parse().unwrap();
+        assert_eq!(url.signed_at(), Some(at(1668544200)));
+        assert_eq!(url.expires_at(), Some(at(1668544200 + 7200)));
+        assert!(url.is_expired());
+        assert_eq!(url.time_remaining(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn sigv2_urls_expire_at_expires() {
+        let url: ResponseUrl = "https://bucket.s3.amazonaws.com/key?AWSAccessKeyId=AKIA&Expires=1668551400&Signature=abc"
+            .parse().unwrap();
+        assert_eq!(url.signed_at(), None);
+        assert_eq!(url.expires_at(), Some(at(1668551400)));
+        assert!(url.is_expired());
+    }
+
+    #[test]
+    fn urls_signed_for_the_future_are_not_expired() {
+        let expires = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 3600;
+        let url: ResponseUrl = format!("https://bucket.s3.amazonaws.com/key?Expires={}&Signature=abc", expires)
+            .parse().unwrap();
+        assert!(!url.is_expired());
+        assert!(url.time_remaining().unwrap() > Duration::ZERO);
+    }
+
+    #[test]
+    fn urls_without_an_expiry_never_expire() {
+        let url: ResponseUrl = "https://bucket.s3.amazonaws.com/key".parse().unwrap();
+        assert_eq!(url.expires_at(), None);
+        assert_eq!(url.time_remaining(), None);
+        assert!(!url.is_expired());
+    }
+}
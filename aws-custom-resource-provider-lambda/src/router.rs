@@ -0,0 +1,201 @@
+use crate::context::ProviderContext;
+use crate::service::dispatch;
+use crate::replacement::handle_replacement;
+use crate::error::{ Error, Error::* };
+use crate::types::*;
+use async_trait::async_trait;
+use aws_custom_resource_provider_events::{
+    ProviderRequestEventDetails,
+    ProviderResponse,
+    ProviderResponseBuilder,
+    ResponseStatus,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::convert::TryFrom;
+use tracing::error;
+
+/// How a route is matched against the ResourceType of an event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResourceTypePattern {
+    /// the whole ResourceType, e.g. `Custom::SsmParameter`
+    Exact(String),
+    /// the start of the ResourceType, e.g. `Custom::Ssm`
+    Prefix(String),
+    /// a pattern where `*` matches any run of characters, e.g. `Custom::*Parameter`
+    Wildcard(String),
+}
+
+impl ResourceTypePattern {
+    pub fn matches(&self, resource_type: &str) -> bool {
+        match self {
+            Self::Exact(name) => name == resource_type,
+            Self::Prefix(prefix) => resource_type.starts_with(prefix.as_str()),
+            Self::Wildcard(pattern) => wildcard_match(pattern, resource_type),
+        }
+    }
+
+    // how the pattern is listed in the FAILED reason for unsupported types
+    fn describe(&self) -> String {
+        match self {
+            Self::Exact(name) => name.clone(),
+            Self::Prefix(prefix) => format!("{}*", prefix),
+            Self::Wildcard(pattern) => pattern.clone(),
+        }
+    }
+}
+
+// greedy glob match supporting only `*`, ResourceType names cannot contain `*` themselves
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    if !value.starts_with(first) {
+        return false;
+    }
+    let mut rest = &value[first.len()..];
+    let mut parts = parts.collect::<Vec<_>>();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(), // no `*` in the pattern
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+// a provider with its event types erased, so differently typed providers can share a router
+#[async_trait]
+pub trait Route: Send + Sync {
+    async fn route(&self, request: &Value, context: &ProviderContext) -> Result<ProviderResponse, Error>;
+    fn sensitive_properties(&self) -> Vec<String>;
+    fn immutable_properties(&self) -> Vec<String>;
+}
+
+#[async_trait]
+impl <P, C, U, D, O> Route for P
+where
+    P: Provider<Create=CreateEvent<C>,
+                Update=UpdateEvent<U, O>,
+                Delete=DeleteEvent<D>> + Send + Sync,
+    C: DeserializeOwned + Send,
+    U: DeserializeOwned + Send,
+    D: DeserializeOwned + Send,
+    O: DeserializeOwned + Send,
+{
+    async fn route(&self, request: &Value, context: &ProviderContext) -> Result<ProviderResponse, Error> {
+        let strategy = Provider::physical_id_strategy(self);
+        handle_replacement(request, &Provider::immutable_properties(self), strategy.as_ref(), |event| async move {
+            let event = ProviderEvent::try_from(event)
+                .map_err(|err| err.redact(&Provider::sensitive_properties(self)))?;
+            Ok(dispatch(self, event, context).await)
+        }).await
+    }
+    fn sensitive_properties(&self) -> Vec<String> {
+        Provider::sensitive_properties(self)
+    }
+    fn immutable_properties(&self) -> Vec<String> {
+        Provider::immutable_properties(self)
+    }
+}
+
+/// A Provider that dispatches on the ResourceType of the event to one of many providers.
+///
+/// Exact names are tried first, then prefix and wildcard patterns in the order they were added,
+/// then the fallback. Events matching none of them get a FAILED response listing the supported types.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(ResourceTypePattern, Box<dyn Route>)>,
+    fallback: Option<Box<dyn Route>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+    pub fn route<R>(self, resource_type: &str, provider: R) -> Router where R: Route + 'static {
+        self.route_pattern(ResourceTypePattern::Exact(resource_type.to_string()), provider)
+    }
+    pub fn route_prefix<R>(self, prefix: &str, provider: R) -> Router where R: Route + 'static {
+        self.route_pattern(ResourceTypePattern::Prefix(prefix.to_string()), provider)
+    }
+    pub fn route_wildcard<R>(self, pattern: &str, provider: R) -> Router where R: Route + 'static {
+        self.route_pattern(ResourceTypePattern::Wildcard(pattern.to_string()), provider)
+    }
+    pub fn route_pattern<R>(mut self, pattern: ResourceTypePattern, provider: R) -> Router where R: Route + 'static {
+        self.routes.push((pattern, Box::new(provider)));
+        self
+    }
+    pub fn fallback<R>(mut self, provider: R) -> Router where R: Route + 'static {
+        self.fallback = Some(Box::new(provider));
+        self
+    }
+
+    /// the patterns this router serves, in the form used when reporting unsupported types
+    pub fn supported_types(&self) -> Vec<String> {
+        self.routes.iter().map(|(pattern, _)| pattern.describe()).collect()
+    }
+
+    fn find(&self, resource_type: &str) -> Option<&dyn Route> {
+        let exact = self.routes.iter()
+            .find(|(pattern, _)| matches!(pattern, ResourceTypePattern::Exact(_)) && pattern.matches(resource_type));
+        let pattern = || self.routes.iter()
+            .find(|(pattern, _)| !matches!(pattern, ResourceTypePattern::Exact(_)) && pattern.matches(resource_type));
+
+        exact.or_else(pattern)
+            .map(|(_, provider)| provider.as_ref())
+            .or(self.fallback.as_deref())
+    }
+
+    async fn dispatch<E>(&self, event: &E, request: Value, context: &ProviderContext) -> ProviderResponse
+    where E: ProviderRequestEventDetails {
+        let resource_type = event.resource_type();
+        let routed = match self.find(&resource_type) {
+            Some(provider) => provider.route(&request, context).await,
+            None => Err(UnsupportedResourceType(resource_type, self.supported_types())),
+        };
+        routed.unwrap_or_else(|err| {
+            error!("Unable to route event: {:?}", &err);
+            ProviderResponseBuilder::from_event_ref(event)
+                .status(ResponseStatus::Failed)
+                .reason(err.reason())
+                .build()
+        })
+    }
+}
+
+// the router receives events with untyped properties, and hands them on in their raw form
+// so that each routed provider can deserialize its own property types.
+#[async_trait]
+impl Provider for Router {
+    type Create = CreateEvent<Value>;
+    type Update = UpdateEvent<Value>;
+    type Delete = DeleteEvent<Value>;
+
+    async fn create(&self, create_event: Self::Create, context: &ProviderContext) -> ProviderResponse {
+        let request = serde_json::to_value(&create_event.0).unwrap_or_default();
+        self.dispatch(&create_event, request, context).await
+    }
+    async fn update(&self, update_event: Self::Update, context: &ProviderContext) -> ProviderResponse {
+        let request = serde_json::to_value(&update_event.0).unwrap_or_default();
+        self.dispatch(&update_event, request, context).await
+    }
+    async fn delete(&self, delete_event: Self::Delete, context: &ProviderContext) -> ProviderResponse {
+        let request = serde_json::to_value(&delete_event.0).unwrap_or_default();
+        self.dispatch(&delete_event, request, context).await
+    }
+    // the event is logged before it is routed, so every routed provider's sensitive fields are masked
+    fn sensitive_properties(&self) -> Vec<String> {
+        let mut sensitive = self.routes.iter()
+            .map(|(_, provider)| provider.as_ref())
+            .chain(self.fallback.as_deref())
+            .flat_map(|provider| provider.sensitive_properties())
+            .collect::<Vec<_>>();
+        sensitive.sort();
+        sensitive.dedup();
+        sensitive
+    }
+}
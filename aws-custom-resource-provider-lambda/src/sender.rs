@@ -0,0 +1,119 @@
+use crate::error::{ Error, Error::* };
+use crate::Client;
+use aws_custom_resource_provider_events::ProviderResponse;
+use hyper::{ StatusCode, Uri };
+use std::time::{ Duration, SystemTime };
+use tracing::{ info, warn, error };
+
+/// Bounded exponential backoff used when writing the response to the presigned S3 URL.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// total number of PUT attempts, including the first one
+    pub max_attempts: u32,
+    /// delay ceiling for the first retry, doubled for every retry after that
+    pub base_delay: Duration,
+    /// upper bound on the delay between any two attempts
+    pub max_delay: Duration,
+    /// how long a single attempt may take, from connecting to reading the reply, before it is abandoned
+    pub attempt_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            attempt_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // "full jitter": a random delay between zero and the exponential ceiling for this retry
+    fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self.base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        Duration::from_millis(fastrand::u64(0..=ceiling.as_millis() as u64))
+    }
+}
+
+// S3 throttling and server side errors are worth another attempt, anything else
+// (403 from an expired presigned URL, 404 for a deleted bucket, ...) will not improve.
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+/// Write the provider response to the presigned S3 URL, retrying transient failures per the policy.
+/// Each attempt is cut short at the deadline, after which Lambda stops the function mid-write, and no retry is started
+/// that could not finish before it. The first attempt is always made, with the full attempt_timeout once the deadline has passed.
+pub async fn send_response(client: &Client, policy: &RetryPolicy, response_url: &Uri, response: &ProviderResponse, deadline: Option<SystemTime>) -> Result<(), Error> {
+    let as_json_response = serde_json::to_string(response)
+        .map_err(InvalidResponse)?;
+
+    let remaining = || deadline.map(|deadline| deadline.duration_since(SystemTime::now()).unwrap_or_default());
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempts = 0;
+    let mut last_failure = String::new();
+
+    while attempts < max_attempts {
+        if attempts > 0 {
+            let delay = policy.delay(attempts - 1);
+            if matches!(remaining(), Some(remaining) if remaining <= delay) {
+                warn!("Not retrying the response PUT, the deadline is too close: {}", &last_failure);
+                break;
+            }
+            warn!("Retrying response PUT in {}ms, attempt {} of {}: {}", delay.as_millis(), attempts + 1, max_attempts, &last_failure);
+            tokio::time::sleep(delay).await;
+        }
+        attempts += 1;
+
+        // the request includes a presigned s3 URL to write our response to
+        let s3_resp = hyper::Request::builder()
+            .method(hyper::Method::PUT)
+            .uri(response_url.clone())
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(as_json_response.clone()))
+            .map_err(|_| InvalidResponseURI())?;
+
+        // a stalled connection is abandoned, rather than left to run into the deadline
+        let timeout = match remaining() {
+            Some(remaining) if !remaining.is_zero() => policy.attempt_timeout.min(remaining),
+            _ => policy.attempt_timeout,
+        };
+        let put = async {
+            let s3_result = client.request(s3_resp).await?;
+            let status = s3_result.status();
+            let body = hyper::body::to_bytes(s3_result.into_body()).await
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .unwrap_or_default();
+            Ok::<_, hyper::Error>((status, body))
+        };
+
+        match tokio::time::timeout(timeout, put).await {
+            Ok(Ok((status, body))) => {
+                if status.is_success() {
+                    info!("Response {:?} written, status: {}, attempts: {}", response.status, status, attempts);
+                    return Ok(());
+                }
+                if !is_retryable(status) {
+                    error!("Response PUT rejected, status: {}, body: {}", status, &body);
+                    return Err(ResponseRejected(status, body));
+                }
+                last_failure = format!("status: {}, body: {}", status, body);
+            },
+            Ok(Err(hyper_err)) => {
+                last_failure = format!("transport error: {}", hyper_err);
+            },
+            Err(_) => {
+                last_failure = format!("no reply within {} ms", timeout.as_millis());
+            },
+        }
+    }
+
+    error!("Response PUT failed after {} attempts, last failure: {}", attempts, &last_failure);
+    Err(ResponseUndeliverable(attempts, last_failure))
+}
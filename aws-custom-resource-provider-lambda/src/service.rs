@@ -0,0 +1,117 @@
+use crate::context::ProviderContext;
+use crate::error::Error;
+use crate::guard::catch_panic;
+use crate::types::*;
+use aws_custom_resource_provider_events::{
+    ProviderResponse,
+    ProviderResponseBuilder,
+};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ Context, Poll };
+use tower::Service;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+pub type ServiceFuture<E> = Pin<Box<dyn Future<Output = Result<ProviderResponse, E>> + Send>>;
+
+/// A typed event together with the context of the invocation it arrived in.
+pub struct ProviderRequest<C, U, D, O = U>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    pub event: ProviderEvent<C, U, D, O>,
+    pub context: ProviderContext,
+}
+
+impl <C, U, D, O> ProviderRequest<C, U, D, O>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    pub fn new(event: ProviderEvent<C, U, D, O>, context: ProviderContext) -> Self {
+        ProviderRequest {
+            event,
+            context,
+        }
+    }
+}
+
+/// The innermost service of the handler, delegating each request to the Provider.
+pub struct ProviderService<P> {
+    provider: Arc<P>,
+}
+
+impl <P> ProviderService<P> {
+    pub fn new(provider: P) -> Self {
+        ProviderService {
+            provider: Arc::new(provider),
+        }
+    }
+}
+
+impl <P> Clone for ProviderService<P> {
+    fn clone(&self) -> Self {
+        ProviderService {
+            provider: self.provider.clone(),
+        }
+    }
+}
+
+impl <P, C, U, D, O> Service<ProviderRequest<C, U, D, O>> for ProviderService<P>
+where
+    P: Provider<Create=CreateEvent<C>,
+                Update=UpdateEvent<U, O>,
+                Delete=DeleteEvent<D>> + Send + Sync + 'static,
+    C: DeserializeOwned + Send + 'static,
+    U: DeserializeOwned + Send + 'static,
+    D: DeserializeOwned + Send + 'static,
+    O: DeserializeOwned + Send + 'static,
+{
+    type Response = ProviderResponse;
+    type Error = Error;
+    type Future = ServiceFuture<Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: ProviderRequest<C, U, D, O>) -> Self::Future {
+        let provider = self.provider.clone();
+        Box::pin(async move {
+            Ok(dispatch(provider.as_ref(), request.event, &request.context).await)
+        })
+    }
+}
+
+// delegate to the request-type-specific handler within the provider.
+// the fallback builders are taken before the events move into the provider, in case it panics
+pub(crate) async fn dispatch<P, C, U, D, O>(provider: &P, event: ProviderEvent<C, U, D, O>, context: &ProviderContext) -> ProviderResponse
+where
+    P: Provider<Create=CreateEvent<C>,
+                Update=UpdateEvent<U, O>,
+                Delete=DeleteEvent<D>>,
+    C: DeserializeOwned,
+    U: DeserializeOwned,
+    D: DeserializeOwned,
+    O: DeserializeOwned,
+{
+    match event {
+        ProviderEvent::Create(event) => {
+            let fallback = ProviderResponseBuilder::from_event_ref(&event);
+            catch_panic(fallback, provider.create(event, context)).await
+        },
+        ProviderEvent::Update(event) => {
+            let fallback = ProviderResponseBuilder::from_event_ref(&event);
+            catch_panic(fallback, provider.update(event, context)).await
+        },
+        ProviderEvent::Delete(event) => {
+            let fallback = ProviderResponseBuilder::from_event_ref(&event);
+            catch_panic(fallback, provider.delete(event, context)).await
+        },
+    }
+}
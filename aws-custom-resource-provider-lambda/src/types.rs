@@ -1,11 +1,21 @@
 use crate::error::{ Error, Error::* };
+use crate::context::ProviderContext;
+use crate::fields;
 use serde_json::{self, Value};
 use aws_custom_resource_provider_events::{
     ProviderRequestCreateEvent,
     ProviderRequestUpdateEvent,
     ProviderRequestDeleteEvent,
+    ProviderRequestEvent,
+    ProviderRequestEventDetails,
+    PhysicalIdStrategy,
+    physical_id::GenerationalHash,
     ProviderResponse,
+    ProviderResponseBuilder,
     RequestType,
+    ResponseStatus,
+    request_type,
+    with_service_properties,
 };
 use async_trait::async_trait;
 use std::convert::TryFrom;
@@ -19,26 +29,54 @@ impl <P> TryFrom<Value> for CreateEvent<P>
 where P: DeserializeOwned {
     type Error = Error;
     fn try_from(value: Value) -> Result<Self, Self::Error> {
-        serde_json::from_value(value)
+        fields::deserialize(&with_service_properties(&value))
             .map(|p| CreateEvent(p))
             .map_err(|e| InvalidEvent(e, RequestType::Create))
     }
 }
 
+impl <P> ProviderRequestEventDetails for CreateEvent<P>
+where P: DeserializeOwned {
+    fn request_type(&self) -> RequestType { self.0.request_type() }
+    fn response_url(&self) -> String { self.0.response_url() }
+    fn stack_id(&self) -> String { self.0.stack_id() }
+    fn request_id(&self) -> String { self.0.request_id() }
+    fn resource_type(&self) -> String { self.0.resource_type() }
+    fn logical_resource_id(&self) -> String { self.0.logical_resource_id() }
+    fn physical_resource_id(&self) -> Option<String> { self.0.physical_resource_id() }
+}
+
 //--- Update Event types
-pub struct UpdateEvent<P>(pub ProviderRequestUpdateEvent<P>)
-where P: DeserializeOwned;
+// O is the type of the OldResourceProperties, see ProviderRequestUpdateEvent
+pub struct UpdateEvent<P, O = P>(pub ProviderRequestUpdateEvent<P, O>)
+where P: DeserializeOwned,
+      O: DeserializeOwned;
 
-impl <P> TryFrom<Value> for UpdateEvent<P>
-where P: DeserializeOwned {
+impl <P, O> TryFrom<Value> for UpdateEvent<P, O>
+where P: DeserializeOwned,
+      O: DeserializeOwned,
+{
     type Error = Error;
     fn try_from(value: Value) -> Result<Self, Self::Error> {
-        serde_json::from_value(value)
+        fields::deserialize(&with_service_properties(&value))
             .map(|p| UpdateEvent(p))
             .map_err(|e| InvalidEvent(e, RequestType::Update))
     }
 }
 
+impl <P, O> ProviderRequestEventDetails for UpdateEvent<P, O>
+where P: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    fn request_type(&self) -> RequestType { self.0.request_type() }
+    fn response_url(&self) -> String { self.0.response_url() }
+    fn stack_id(&self) -> String { self.0.stack_id() }
+    fn request_id(&self) -> String { self.0.request_id() }
+    fn resource_type(&self) -> String { self.0.resource_type() }
+    fn logical_resource_id(&self) -> String { self.0.logical_resource_id() }
+    fn physical_resource_id(&self) -> Option<String> { self.0.physical_resource_id() }
+}
+
 //--- Delete Event types
 pub struct DeleteEvent<P>(pub ProviderRequestDeleteEvent<P>)
 where P: DeserializeOwned;
@@ -47,12 +85,104 @@ impl <P> TryFrom<Value> for DeleteEvent<P>
 where P: DeserializeOwned {
     type Error = Error;
     fn try_from(value: Value) -> Result<Self, Self::Error> {
-        serde_json::from_value(value)
+        fields::deserialize(&with_service_properties(&value))
             .map(|p| DeleteEvent(p))
             .map_err(|e| InvalidEvent(e, RequestType::Delete))
     }
 }
 
+impl <P> ProviderRequestEventDetails for DeleteEvent<P>
+where P: DeserializeOwned {
+    fn request_type(&self) -> RequestType { self.0.request_type() }
+    fn response_url(&self) -> String { self.0.response_url() }
+    fn stack_id(&self) -> String { self.0.stack_id() }
+    fn request_id(&self) -> String { self.0.request_id() }
+    fn resource_type(&self) -> String { self.0.resource_type() }
+    fn logical_resource_id(&self) -> String { self.0.logical_resource_id() }
+    fn physical_resource_id(&self) -> Option<String> { self.0.physical_resource_id() }
+}
+
+
+
+//--- Any of the typed events, as a single request for services and layers
+pub enum ProviderEvent<C, U, D, O = U>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    Create(CreateEvent<C>),
+    Update(UpdateEvent<U, O>),
+    Delete(DeleteEvent<D>),
+}
+
+impl <C, U, D, O> From<ProviderRequestEvent<C, U, D, O>> for ProviderEvent<C, U, D, O>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    fn from(event: ProviderRequestEvent<C, U, D, O>) -> Self {
+        match event {
+            ProviderRequestEvent::Create(event) => ProviderEvent::Create(CreateEvent(event)),
+            ProviderRequestEvent::Update(event) => ProviderEvent::Update(UpdateEvent(event)),
+            ProviderRequestEvent::Delete(event) => ProviderEvent::Delete(DeleteEvent(event)),
+        }
+    }
+}
+
+impl <C, U, D, O> TryFrom<Value> for ProviderEvent<C, U, D, O>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    type Error = Error;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        use serde::Deserialize;
+        let value = with_service_properties(&value);
+        // the tagged enum buffers the event to find its RequestType, which loses the path of a failure.
+        // only when that happens is the event deserialized again, as its own type, to report every field that failed.
+        ProviderRequestEvent::<C, U, D, O>::deserialize(&value)
+            .map(ProviderEvent::from)
+            .or_else(|_| match request_type(&value) {
+                Some(RequestType::Create) => CreateEvent::try_from(value).map(ProviderEvent::Create),
+                Some(RequestType::Update) => UpdateEvent::try_from(value).map(ProviderEvent::Update),
+                Some(RequestType::Delete) => DeleteEvent::try_from(value).map(ProviderEvent::Delete),
+                None => Err(InvalidRequestType(value)),
+            })
+    }
+}
+
+impl <C, U, D, O> ProviderRequestEventDetails for ProviderEvent<C, U, D, O>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    fn request_type(&self) -> RequestType { self.details().request_type() }
+    fn response_url(&self) -> String { self.details().response_url() }
+    fn stack_id(&self) -> String { self.details().stack_id() }
+    fn request_id(&self) -> String { self.details().request_id() }
+    fn resource_type(&self) -> String { self.details().resource_type() }
+    fn logical_resource_id(&self) -> String { self.details().logical_resource_id() }
+    fn physical_resource_id(&self) -> Option<String> { self.details().physical_resource_id() }
+}
+
+impl <C, U, D, O> ProviderEvent<C, U, D, O>
+where C: DeserializeOwned,
+      U: DeserializeOwned,
+      D: DeserializeOwned,
+      O: DeserializeOwned,
+{
+    fn details(&self) -> &dyn ProviderRequestEventDetails {
+        match self {
+            ProviderEvent::Create(event) => event,
+            ProviderEvent::Update(event) => event,
+            ProviderEvent::Delete(event) => event,
+        }
+    }
+}
 
 
 #[async_trait]
@@ -61,7 +191,93 @@ pub trait Provider {
     type Update: TryFrom<Value, Error=Error>;
     type Delete: TryFrom<Value, Error=Error>;
 
-    async fn create(&self, create_event: Self::Create) -> ProviderResponse;
-    async fn update(&self, update_event: Self::Update) -> ProviderResponse;
-    async fn delete(&self, delete_event: Self::Delete) -> ProviderResponse;
+    async fn create(&self, create_event: Self::Create, context: &ProviderContext) -> ProviderResponse;
+    async fn update(&self, update_event: Self::Update, context: &ProviderContext) -> ProviderResponse;
+    async fn delete(&self, delete_event: Self::Delete, context: &ProviderContext) -> ProviderResponse;
+
+    // names of the ResourceProperties fields to mask when the event is logged, dotted for nested fields
+    fn sensitive_properties(&self) -> Vec<String> { Vec::new() }
+
+    // names of the ResourceProperties fields that cannot change in place, dotted for nested fields.
+    // an Update changing any of them is handled by create, as the replacement of the resource.
+    fn immutable_properties(&self) -> Vec<String> { Vec::new() }
+
+    // how a resource is named when create answers with the default id, or a replacement with the id of the resource it replaces
+    fn physical_id_strategy(&self) -> Box<dyn PhysicalIdStrategy> { Box::new(GenerationalHash) }
+}
+
+// A Provider whose methods may fail, every Err is reported to CloudFormation as a FAILED response
+// with the error and its sources as the Reason. Implementors get Provider for free.
+#[async_trait]
+pub trait FallibleProvider {
+    type Create: TryFrom<Value, Error=Error> + ProviderRequestEventDetails + Send;
+    type Update: TryFrom<Value, Error=Error> + ProviderRequestEventDetails + Send;
+    type Delete: TryFrom<Value, Error=Error> + ProviderRequestEventDetails + Send;
+    type Error: std::error::Error + Send;
+
+    async fn create(&self, create_event: Self::Create, context: &ProviderContext) -> Result<ProviderResponse, Self::Error>;
+    async fn update(&self, update_event: Self::Update, context: &ProviderContext) -> Result<ProviderResponse, Self::Error>;
+    async fn delete(&self, delete_event: Self::Delete, context: &ProviderContext) -> Result<ProviderResponse, Self::Error>;
+
+    // names of the ResourceProperties fields to mask when the event is logged, dotted for nested fields
+    fn sensitive_properties(&self) -> Vec<String> { Vec::new() }
+
+    // names of the ResourceProperties fields that cannot change in place, dotted for nested fields.
+    // an Update changing any of them is handled by create, as the replacement of the resource.
+    fn immutable_properties(&self) -> Vec<String> { Vec::new() }
+
+    // how a resource is named when create answers with the default id, or a replacement with the id of the resource it replaces
+    fn physical_id_strategy(&self) -> Box<dyn PhysicalIdStrategy> { Box::new(GenerationalHash) }
+}
+
+#[async_trait]
+impl <F> Provider for F
+where F: FallibleProvider + Sync {
+    type Create = F::Create;
+    type Update = F::Update;
+    type Delete = F::Delete;
+
+    async fn create(&self, create_event: Self::Create, context: &ProviderContext) -> ProviderResponse {
+        let fallback = ProviderResponseBuilder::from_event_ref(&create_event);
+        FallibleProvider::create(self, create_event, context).await
+            .unwrap_or_else(|err| failed_response(fallback, &err))
+    }
+    async fn update(&self, update_event: Self::Update, context: &ProviderContext) -> ProviderResponse {
+        let fallback = ProviderResponseBuilder::from_event_ref(&update_event);
+        FallibleProvider::update(self, update_event, context).await
+            .unwrap_or_else(|err| failed_response(fallback, &err))
+    }
+    async fn delete(&self, delete_event: Self::Delete, context: &ProviderContext) -> ProviderResponse {
+        let fallback = ProviderResponseBuilder::from_event_ref(&delete_event);
+        FallibleProvider::delete(self, delete_event, context).await
+            .unwrap_or_else(|err| failed_response(fallback, &err))
+    }
+    fn sensitive_properties(&self) -> Vec<String> {
+        FallibleProvider::sensitive_properties(self)
+    }
+    fn immutable_properties(&self) -> Vec<String> {
+        FallibleProvider::immutable_properties(self)
+    }
+    fn physical_id_strategy(&self) -> Box<dyn PhysicalIdStrategy> {
+        FallibleProvider::physical_id_strategy(self)
+    }
+}
+
+fn failed_response(fallback: ProviderResponseBuilder, err: &dyn std::error::Error) -> ProviderResponse {
+    fallback
+        .status(ResponseStatus::Failed)
+        .reason(error_chain(err))
+        .build()
+}
+
+/// Render an error followed by each of its sources, separated by ": ".
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        chain.push_str(": ");
+        chain.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    chain
 }